@@ -1,77 +1,392 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Debug,
+    fs::{metadata, read_to_string},
+    iter::once,
+    ops::Range,
+    path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicU32, Ordering},
-        Arc, RwLock,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
     },
+    time::Duration,
 };
 
 use bimap::BiHashMap;
+use chrono::{DateTime, Local, NaiveDate, Utc};
 use nadylib::{
-    client_socket::SocketSendHandle,
-    models::{Channel, Message},
+    models::{Channel, ChannelType as GroupType, Message},
     packets::{
         ClientLookupPacket, GroupMessagePacket, LoginSelectPacket, MsgPrivatePacket,
-        OutPrivgrpInvitePacket, OutPrivgrpKickPacket, PrivgrpMessagePacket, PrivgrpPartPacket,
+        OutPrivgrpInvitePacket, OutPrivgrpKickPacket, OutgoingPacket, PingPacket,
+        PrivgrpJoinPacket, PrivgrpMessagePacket, PrivgrpPartPacket,
     },
-    AOSocket, ReceivedPacket,
+    ReceivedPacket,
 };
-use tokio::sync::{
-    mpsc::{UnboundedReceiver, UnboundedSender},
-    oneshot::Sender,
-    Notify,
+use tokio::{
+    sync::{
+        mpsc::{UnboundedReceiver, UnboundedSender},
+        oneshot::Sender,
+        Notify,
+    },
+    time::{interval_at, sleep, sleep_until, timeout, Instant},
+};
+use tui::{
+    style::{Modifier, Style},
+    text::{Span, Spans},
+};
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    command,
+    config::{self, ChannelPrefixes, Config, Decoration},
+    highlight::Highlighter,
+    lists::{ChannelAliases, WordList},
+    markup::{self, Link},
+    packet_log::PacketLog,
+    responses::AutoResponses,
+    theme::Theme,
+    transport::{Connector, PacketSender, PacketSocket},
 };
-use tui::text::{Span, Spans};
 
-use crate::command;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+/// Least time between two packets sent to the server, so bulk sends like invites or
+/// the outbox, and anything typed meanwhile, are not dropped
+const SEND_INTERVAL: Duration = Duration::from_millis(500);
+const UNKNOWN_GROUP: &str = "Unknown Group";
+/// Longest message in bytes, as counted by the input and checked by `/sendfile`
+pub const MESSAGE_BYTE_LIMIT: usize = 1024;
+/// Largest file `/sendfile` accepts, to not flood a channel by accident
+const SEND_FILE_LIMIT: u64 = 16 * 1024;
+/// A session lost within this time after logging in counts as dropped by the server
+const QUICK_DROP_TIME: Duration = Duration::from_secs(30);
+/// Sessions dropped quickly in a row after which the client stops reconnecting. The
+/// server drops the older session when a character logs in twice, so two clients on
+/// the same character would otherwise keep logging each other out.
+const QUICK_DROP_LIMIT: u32 = 3;
 
 pub enum StateQuery {
     Channels(Sender<Vec<ResolvedChannel>>),
+    Diagnostics(Sender<Diagnostics>),
+    Invites(Sender<Vec<ResolvedChannel>>),
+}
+
+/// Connection health, for the diagnostics overlay.
+pub struct Diagnostics {
+    /// Received packets by type, most frequent first
+    pub packet_counts: Vec<(&'static str, u64)>,
+    pub last_packet: Option<DateTime<Local>>,
+    pub reconnects: u64,
+    /// Messages and tells waiting in the outbox
+    pub queued: usize,
+}
+
+/// Name of the packet type, as counted in the diagnostics.
+fn packet_type(packet: &ReceivedPacket) -> &'static str {
+    match packet {
+        ReceivedPacket::LoginSeed(_) => "LoginSeed",
+        ReceivedPacket::LoginOk => "LoginOk",
+        ReceivedPacket::LoginError(_) => "LoginError",
+        ReceivedPacket::LoginCharlist(_) => "LoginCharlist",
+        ReceivedPacket::ClientName(_) => "ClientName",
+        ReceivedPacket::ClientLookup(_) => "ClientLookup",
+        ReceivedPacket::MsgPrivate(_) => "MsgPrivate",
+        ReceivedPacket::MsgVicinity(_) => "MsgVicinity",
+        ReceivedPacket::MsgVicinitya(_) => "MsgVicinitya",
+        ReceivedPacket::MsgSystem(_) => "MsgSystem",
+        ReceivedPacket::ChatNotice(_) => "ChatNotice",
+        ReceivedPacket::BuddyStatus(_) => "BuddyStatus",
+        ReceivedPacket::BuddyRemove(_) => "BuddyRemove",
+        ReceivedPacket::PrivgrpInvite(_) => "PrivgrpInvite",
+        ReceivedPacket::PrivgrpKick(_) => "PrivgrpKick",
+        ReceivedPacket::PrivgrpClijoin(_) => "PrivgrpClijoin",
+        ReceivedPacket::PrivgrpClipart(_) => "PrivgrpClipart",
+        ReceivedPacket::PrivgrpMessage(_) => "PrivgrpMessage",
+        ReceivedPacket::GroupAnnounce(_) => "GroupAnnounce",
+        ReceivedPacket::GroupMessage(_) => "GroupMessage",
+        ReceivedPacket::Ping(_) => "Ping",
+    }
+}
+
+pub enum Recipient {
+    Name(String),
+    LastSender(ResolvedChannel),
 }
 
 pub enum Command {
-    Invite(String),
-    Kick(String),
+    Invite(Recipient),
+    InviteAll(Vec<String>),
+    InviteFile(PathBuf),
+    Kick(Recipient),
     Leave(String),
-    Tell(String, String),
+    Join(String),
+    JoinId(u32),
+    Tell(Vec<String>, String),
+    Org(String),
+    Broadcast(String),
+    /// Sends a file line by line to a channel
+    SendFile(String, PathBuf),
+    Seen(String),
+    Reconnect,
+    Debug,
+    Stats,
+    Server,
+    WhoAmI,
+    Ignore(String, bool),
+    Highlight(String, bool),
+    Close(String),
+    Mute(String, bool),
+    /// Sets or removes the display name of a channel
+    ChannelAlias(String, Option<String>),
+    /// Lists the outbox, or cancels it if true
+    Outbox(bool),
+    ClearInvites,
+    Message(ResolvedChannel, String),
+}
+
+/// What became of a message handed to `send_message`.
+pub enum SendOutcome {
+    Sent,
+    /// Held back in the outbox until the login completes
+    Queued,
+    Failed,
+}
+
+/// Something submitted while not logged in, sent once the login completes.
+pub enum Queued {
     Message(ResolvedChannel, String),
+    Tell(Vec<String>, String),
+}
+
+impl Queued {
+    fn describe(&self) -> String {
+        match self {
+            Self::Message(channel, text) => format!("[{}] {}", channel.render(), text),
+            Self::Tell(users, text) => format!("[{}] {}", users.join(", "), text),
+        }
+    }
 }
 
-impl From<command::Command> for Command {
-    fn from(cmd: command::Command) -> Self {
-        match cmd {
-            command::Command::Invite(user) => Self::Invite(user),
-            command::Command::Kick(user) => Self::Kick(user),
+impl Command {
+    /// Turns a parsed command into one for the chat task, resolving implicit targets
+    /// against the channel the user is currently in. Returns `None` for commands
+    /// that are handled by the UI itself.
+    pub fn new(cmd: command::Command, current_channel: &ResolvedChannel) -> Option<Self> {
+        let recipient = |user: Option<String>| {
+            user.map_or_else(
+                || Recipient::LastSender(current_channel.clone()),
+                Recipient::Name,
+            )
+        };
+
+        let cmd = match cmd {
+            command::Command::Invite(user) => Self::Invite(recipient(user)),
+            command::Command::InviteAll(users) => Self::InviteAll(users),
+            command::Command::InviteFile(path) => Self::InviteFile(PathBuf::from(path)),
+            command::Command::Kick(user) => Self::Kick(recipient(user)),
             command::Command::Leave(user) => Self::Leave(user),
             command::Command::Tell(user, message) => Self::Tell(user, message),
-        }
+            command::Command::Org(message) => Self::Org(message),
+            command::Command::Broadcast(message) => Self::Broadcast(message),
+            command::Command::SendFile(channel, path) => {
+                Self::SendFile(channel, PathBuf::from(path))
+            }
+            command::Command::Seen(user) => Self::Seen(user),
+            command::Command::Reconnect => Self::Reconnect,
+            command::Command::Debug => Self::Debug,
+            command::Command::Stats => Self::Stats,
+            command::Command::Server => Self::Server,
+            command::Command::WhoAmI => Self::WhoAmI,
+            command::Command::MessageId(id, message) => Self::Message(
+                ResolvedChannel {
+                    id,
+                    name: id.to_string(),
+                    r#type: ChannelType::PrivateChannel,
+                    alias: None,
+                    prefixes: current_channel.prefixes.clone(),
+                },
+                message,
+            ),
+            command::Command::Join(user) => Self::Join(user),
+            command::Command::JoinId(id) => Self::JoinId(id),
+            command::Command::Ignore(user) => Self::Ignore(user, true),
+            command::Command::Unignore(user) => Self::Ignore(user, false),
+            command::Command::Highlight(word) => Self::Highlight(word, true),
+            command::Command::Unhighlight(word) => Self::Highlight(word, false),
+            command::Command::Close(user) => Self::Close(user),
+            command::Command::Mute(channel) => Self::Mute(channel, true),
+            command::Command::Unmute(channel) => Self::Mute(channel, false),
+            command::Command::Outbox(clear) => Self::Outbox(clear),
+            command::Command::ClearInvites => Self::ClearInvites,
+            command::Command::ChannelAlias(channel, alias) => Self::ChannelAlias(channel, alias),
+            command::Command::Channels
+            | command::Command::Export(_)
+            | command::Command::Toggle(_)
+            | command::Command::Topic(_)
+            | command::Command::Quote(_)
+            | command::Command::Invites
+            | command::Command::JoinInvite(_)
+            | command::Command::Help => return None,
+        };
+
+        Some(cmd)
     }
 }
 
 pub enum UiUpdate {
     Message(ResolvedMessage),
     Invite(ResolvedChannel),
-    Kick(ResolvedChannel),
-    Leave(String, ResolvedChannel),
+    Kick(String, ResolvedChannel),
+    Leave(ResolvedChannel),
+    /// The owner of a private channel kicked us out of it
+    Kicked(ResolvedChannel),
+    /// The chat task gave up, the UI should exit with this reason
+    Fatal(String),
+    /// A tell conversation was removed from the channel list
+    Closed(ResolvedChannel),
+    Channels(Vec<ResolvedChannel>),
+    System(String),
+    Status(String),
+    Latency(Duration),
+    Delivery(u64, Delivery),
+    /// The runtime highlight words changed
+    Highlights(Vec<String>),
+    /// The set of muted channels changed
+    Muted(Vec<(ChannelType, u32)>),
+    /// A tell to a single character went out
+    TellSent(ResolvedChannel),
+    Connection(ConnectionState),
+    /// Logged in again after the connection was lost
+    Reconnected,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Authenticating,
+    Ready,
+    Reconnecting,
+    Disconnected,
+}
+
+impl ConnectionState {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::Connecting => "connecting",
+            Self::Authenticating => "authenticating",
+            Self::Ready => "ready",
+            Self::Reconnecting => "reconnecting",
+            Self::Disconnected => "disconnected",
+        }
+    }
+}
+
+/// Delivery state of a locally echoed message.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    Pending,
+    Sent,
+    Failed,
+}
+
+impl Delivery {
+    pub fn marker(self) -> &'static str {
+        match self {
+            Self::Pending => "… ",
+            Self::Sent => "✓ ",
+            Self::Failed => "✗ ",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ChannelType {
     Group,
+    Org,
     PrivateChannel,
     Tell,
     Vicinity,
+    AnonymousVicinity,
+}
+
+impl ChannelType {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::Group => "group",
+            Self::Org => "org",
+            Self::PrivateChannel => "private channel",
+            Self::Tell => "tell",
+            Self::Vicinity => "vicinity",
+            Self::AnonymousVicinity => "anonymous vicinity",
+        }
+    }
+}
+
+/// Which parts of the prefix are shown in front of messages.
+#[derive(Clone, Copy)]
+pub struct RenderOptions {
+    pub timestamps: bool,
+    pub channels: bool,
+    /// Show timestamps in UTC instead of local time
+    pub utc: bool,
+    /// Show message ids, for `/quote`
+    pub ids: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            timestamps: true,
+            channels: true,
+            utc: false,
+            ids: false,
+        }
+    }
+}
+
+impl RenderOptions {
+    /// Formats the timestamp in front of a line, or nothing if timestamps are hidden.
+    pub fn timestamp(self, time: DateTime<Local>) -> String {
+        if !self.timestamps {
+            String::new()
+        } else if self.utc {
+            time.with_timezone(&Utc).format("%H:%M ").to_string()
+        } else {
+            time.format("%H:%M ").to_string()
+        }
+    }
+
+    /// The day `time` falls on in the zone timestamps are shown in.
+    pub fn date(self, time: DateTime<Local>) -> NaiveDate {
+        if self.utc {
+            time.with_timezone(&Utc).date_naive()
+        } else {
+            time.date_naive()
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct ResolvedMessage {
     pub sender: Option<String>,
     pub channel: ResolvedChannel,
+    /// Text with AO's markup stripped
     pub text: String,
+    /// Links from the markup, styled like URLs
+    pub links: Vec<Link>,
+    pub outgoing: bool,
+    pub timestamp: DateTime<Local>,
+    /// Echo id and delivery state for messages echoed before they were sent
+    pub delivery: Option<(u64, Delivery)>,
+    /// How often the message was received in a row, if repeats are coalesced
+    pub repeats: u32,
+    /// Number of the message in this session, assigned by the UI when it is shown
+    pub id: u64,
 }
 
 impl ResolvedMessage {
-    fn new(state: &ChatState, message: &Message) -> Self {
+    fn new<S: PacketSender>(state: &ChatState<S>, message: &Message) -> Self {
         let sender = message.sender.map(|id| {
             state
                 .user_lookup
@@ -82,40 +397,190 @@ impl ResolvedMessage {
                 .to_owned()
         });
         let channel = ResolvedChannel::new(state, &message.channel);
+        let outgoing = message.sender == Some(state.current_user.load(Ordering::Relaxed));
+        let (text, links) = markup::parse(&message.text);
 
         Self {
             sender,
             channel,
-            text: message.text.clone(),
+            text,
+            links,
+            outgoing,
+            timestamp: Local::now(),
+            delivery: None,
+            repeats: 1,
+            id: 0,
         }
     }
 
-    pub fn render<'a>(&self) -> Vec<Spans<'a>> {
-        let channel = self.channel.render();
-
-        let text = if let Some(sender) = &self.sender {
-            format!("[{}] {}: {}", channel, sender, self.text)
+    fn prefix(&self, options: RenderOptions) -> String {
+        let time = options.timestamp(self.timestamp);
+        let direction = if self.outgoing { "→ " } else { "" };
+        let channel = if options.channels {
+            format!("[{}] ", self.channel.render())
         } else {
-            format!("[{}] {}", channel, self.text)
+            String::new()
         };
-        let lines = text.split("\n");
-        let spans: Vec<Spans> = lines
-            .map(|line| Spans::from(Span::raw(line.to_string())))
+
+        if let Some(sender) = &self.sender {
+            format!("{}{}{}{}: ", time, direction, channel, sender)
+        } else {
+            format!("{}{}{}", time, direction, channel)
+        }
+    }
+
+    fn style(&self, theme: &Theme) -> Style {
+        if self.outgoing {
+            Style::default().fg(theme.outgoing)
+        } else if let ChannelType::Tell = self.channel.r#type {
+            Style::default().fg(theme.tell)
+        } else {
+            Style::default()
+        }
+    }
+
+    pub fn render<'a>(
+        &self,
+        theme: &Theme,
+        highlighter: &Highlighter,
+        options: RenderOptions,
+    ) -> Vec<Spans<'a>> {
+        self.render_with_prefix(
+            &self.prefix(options),
+            None,
+            self.shown_id(options),
+            theme,
+            highlighter,
+        )
+    }
+
+    /// Renders only the text, indented to line up with a previous message's prefix.
+    pub fn render_continuation<'a>(
+        &self,
+        theme: &Theme,
+        highlighter: &Highlighter,
+        options: RenderOptions,
+    ) -> Vec<Spans<'a>> {
+        let indent = " ".repeat(self.prefix(options).width());
+        self.render_with_prefix(
+            &indent,
+            Some(&indent),
+            self.shown_id(options),
+            theme,
+            highlighter,
+        )
+    }
+
+    fn shown_id(&self, options: RenderOptions) -> Option<u64> {
+        if options.ids && self.id > 0 {
+            Some(self.id)
+        } else {
+            None
+        }
+    }
+
+    fn render_with_prefix<'a>(
+        &self,
+        prefix: &str,
+        line_indent: Option<&str>,
+        id: Option<u64>,
+        theme: &Theme,
+        highlighter: &Highlighter,
+    ) -> Vec<Spans<'a>> {
+        let style = self.style(theme);
+        let highlight_style = Style::default()
+            .fg(theme.highlight)
+            .add_modifier(Modifier::BOLD);
+        let link_style = Style::default()
+            .fg(theme.link)
+            .add_modifier(Modifier::UNDERLINED);
+        // Highlights win over links they overlap with
+        let highlights = highlighter.find(&self.text);
+        let mut ranges: Vec<(Range<usize>, Style)> = highlighter
+            .find_links(&self.text)
+            .into_iter()
+            .chain(self.links.iter().map(|link| link.range.clone()))
+            .filter(|link| {
+                !highlights
+                    .iter()
+                    .any(|h| h.start < link.end && link.start < h.end)
+            })
+            .map(|link| (link, link_style))
+            .chain(highlights.iter().map(|h| (h.clone(), highlight_style)))
             .collect();
+        ranges.sort_by_key(|(range, _)| range.start);
+        let last_line = self.text.matches('\n').count();
 
-        spans
+        let mut offset = 0;
+        self.text
+            .split('\n')
+            .enumerate()
+            .map(|(i, line)| {
+                let lead = if i == 0 { Some(prefix) } else { line_indent };
+                let mut spans: Vec<Span> = lead
+                    .map(|lead| Span::styled(lead.to_string(), style))
+                    .into_iter()
+                    .collect();
+                if let (0, Some(id)) = (i, id) {
+                    let dim = Style::default().add_modifier(Modifier::DIM);
+                    spans.insert(0, Span::styled(format!("#{} ", id), dim));
+                }
+                if let (0, Some((_, delivery))) = (i, self.delivery) {
+                    // Always the first span, so the UI can swap it once the send completes
+                    spans.insert(0, Span::styled(delivery.marker(), style));
+                }
+
+                let mut position = 0;
+                for (range, range_style) in &ranges {
+                    let start = range.start.saturating_sub(offset).min(line.len());
+                    let end = range.end.saturating_sub(offset).min(line.len());
+                    if start >= end || start < position {
+                        continue;
+                    }
+                    spans.push(Span::styled(line[position..start].to_string(), style));
+                    spans.push(Span::styled(line[start..end].to_string(), *range_style));
+                    position = end;
+                }
+                spans.push(Span::styled(line[position..].to_string(), style));
+                if i == last_line && self.repeats > 1 {
+                    spans.push(Span::styled(
+                        format!(" (x{})", self.repeats),
+                        Style::default().fg(theme.system),
+                    ));
+                }
+
+                offset += line.len() + 1;
+                Spans::from(spans)
+            })
+            .collect()
     }
 }
 
+pub fn render_system_message<'a>(
+    text: &str,
+    time: DateTime<Local>,
+    theme: &Theme,
+    options: RenderOptions,
+) -> Vec<Spans<'a>> {
+    let time = options.timestamp(time);
+    let style = Style::default().fg(theme.system);
+    text.split('\n')
+        .map(|line| Spans::from(Span::styled(format!("{}-- {}", time, line), style)))
+        .collect()
+}
+
 #[derive(Clone)]
 pub struct ResolvedChannel {
     pub id: u32,
     pub name: String,
     pub r#type: ChannelType,
+    /// Local display name, set with `/alias`
+    pub alias: Option<String>,
+    pub prefixes: Arc<ChannelPrefixes>,
 }
 
 impl ResolvedChannel {
-    fn new(state: &ChatState, channel: &Channel) -> Self {
+    fn new<S: PacketSender>(state: &ChatState<S>, channel: &Channel) -> Self {
         let (name, id, r#type) = match channel {
             Channel::Group(group) => (
                 group.name.clone().unwrap_or_else(|| {
@@ -127,7 +592,7 @@ impl ResolvedChannel {
                         .find_map(|c| {
                             if let Channel::Group(g) = c {
                                 if group.id == g.id {
-                                    Some(g.name.as_ref().unwrap())
+                                    g.name.clone()
                                 } else {
                                     None
                                 }
@@ -135,23 +600,18 @@ impl ResolvedChannel {
                                 None
                             }
                         })
-                        .unwrap()
-                        .to_string()
+                        // The announce for this group has not arrived yet
+                        .unwrap_or_else(|| String::from(UNKNOWN_GROUP))
                 }),
                 group.id,
-                ChannelType::Group,
-            ),
-            Channel::PrivateChannel(id) => (
-                state
-                    .user_lookup
-                    .read()
-                    .unwrap()
-                    .get_by_left(&id)
-                    .unwrap()
-                    .to_string(),
-                *id,
-                ChannelType::PrivateChannel,
+                if group.r#type == GroupType::Org {
+                    ChannelType::Org
+                } else {
+                    ChannelType::Group
+                },
             ),
+            // Channels joined by id may belong to a character we never got a name for
+            Channel::PrivateChannel(id) => (state.user_name(*id), *id, ChannelType::PrivateChannel),
             Channel::Tell(id) => (
                 state
                     .user_lookup
@@ -166,49 +626,269 @@ impl ResolvedChannel {
             Channel::Vicinity => (String::from("Vicinity"), 0, ChannelType::Vicinity),
         };
 
-        Self { name, id, r#type }
+        let mut resolved = Self {
+            name,
+            id,
+            r#type,
+            alias: None,
+            prefixes: state.prefixes.clone(),
+        };
+        resolved.alias = state
+            .channel_aliases
+            .read()
+            .unwrap()
+            .get(&resolved.real_name())
+            .map(str::to_string);
+        resolved
+    }
+
+    pub fn vicinity(prefixes: &Arc<ChannelPrefixes>) -> Self {
+        Self {
+            id: 0,
+            name: String::from("Vicinity"),
+            r#type: ChannelType::Vicinity,
+            alias: None,
+            prefixes: prefixes.clone(),
+        }
+    }
+
+    pub fn key(&self) -> (ChannelType, u32) {
+        (self.r#type, self.id)
     }
 
+    /// The channel as shown to the user, by its alias if it has one.
     pub fn render(&self) -> String {
+        self.render_name(self.alias.as_ref().unwrap_or(&self.name))
+    }
+
+    /// The channel by its real name, regardless of any alias.
+    pub fn real_name(&self) -> String {
+        self.render_name(&self.name)
+    }
+
+    /// Whether the user means this channel by `name`, which can be the rendered name,
+    /// the alias or the real name.
+    pub fn is_named(&self, name: &str) -> bool {
+        self.render().eq_ignore_ascii_case(name)
+            || self.real_name().eq_ignore_ascii_case(name)
+            || self.name.eq_ignore_ascii_case(name)
+    }
+
+    fn render_name(&self, name: &str) -> String {
+        let prefix = self.prefixes.get(self.r#type);
         match self.r#type {
-            ChannelType::Group => {
-                format!("*{}", self.name)
-            }
-            ChannelType::PrivateChannel => {
-                format!("#{}", self.name)
-            }
-            ChannelType::Tell => {
-                format!("@{}", self.name)
-            }
-            ChannelType::Vicinity => String::from("."),
+            ChannelType::Group
+            | ChannelType::Org
+            | ChannelType::PrivateChannel
+            | ChannelType::Tell => format!("{}{}", prefix, name),
+            ChannelType::Vicinity => prefix.to_string(),
+            ChannelType::AnonymousVicinity => prefix.repeat(2),
         }
     }
 }
 
-pub struct ChatState {
+pub struct ChatState<S> {
     pub channels: RwLock<Vec<Channel>>,
     pub past_invites: RwLock<Vec<Channel>>,
     pub user_lookup: RwLock<BiHashMap<u32, String>>,
     pub current_user: AtomicU32,
     pub pending_lookups: RwLock<HashMap<String, Arc<Notify>>>,
+    pub buddy_status: RwLock<HashMap<u32, (bool, DateTime<Local>)>>,
+    pub last_senders: RwLock<HashMap<(ChannelType, u32), u32>>,
+    pub next_echo_id: AtomicU64,
+    pub ignored: RwLock<WordList>,
+    pub highlights: RwLock<WordList>,
+    pub channel_aliases: RwLock<ChannelAliases>,
+    pub prefixes: Arc<ChannelPrefixes>,
+    /// Channels whose incoming messages are dropped, for this session
+    pub muted: RwLock<HashSet<(ChannelType, u32)>>,
+    /// Messages and tells waiting for the login to complete
+    pub outbox: RwLock<VecDeque<Queued>>,
+    pub logged_in: AtomicBool,
+    pub send_tag: String,
+    pub decoration: Decoration,
+    pub started: Instant,
+    pub received_count: AtomicU64,
+    pub sent_count: AtomicU64,
+    pub channel_counts: RwLock<HashMap<String, u64>>,
+    pub packet_counts: RwLock<HashMap<&'static str, u64>>,
+    pub last_packet: RwLock<Option<DateTime<Local>>>,
+    pub reconnects: AtomicU64,
+    /// Earliest time the next packet may be sent, see `send`
+    pub next_send: Mutex<Instant>,
     pub ui_update_sender: UnboundedSender<UiUpdate>,
-    pub sender: SocketSendHandle,
+    pub sender: RwLock<S>,
+    pub read_only: bool,
 }
 
-impl ChatState {
-    pub fn new(sender: SocketSendHandle, ui_update_sender: UnboundedSender<UiUpdate>) -> Self {
+impl<S: PacketSender> ChatState<S> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        sender: S,
+        ui_update_sender: UnboundedSender<UiUpdate>,
+        read_only: bool,
+        ignored: WordList,
+        highlights: WordList,
+        channel_aliases: ChannelAliases,
+        send_tag: String,
+        decoration: Decoration,
+        prefixes: Arc<ChannelPrefixes>,
+    ) -> Self {
         Self {
             channels: RwLock::new(Vec::new()),
             past_invites: RwLock::new(Vec::new()),
             user_lookup: RwLock::new(BiHashMap::new()),
             current_user: AtomicU32::new(0),
             pending_lookups: RwLock::new(HashMap::new()),
-            sender,
+            buddy_status: RwLock::new(HashMap::new()),
+            last_senders: RwLock::new(HashMap::new()),
+            next_echo_id: AtomicU64::new(0),
+            ignored: RwLock::new(ignored),
+            highlights: RwLock::new(highlights),
+            channel_aliases: RwLock::new(channel_aliases),
+            prefixes,
+            muted: RwLock::new(HashSet::new()),
+            outbox: RwLock::new(VecDeque::new()),
+            logged_in: AtomicBool::new(false),
+            send_tag,
+            decoration,
+            started: Instant::now(),
+            received_count: AtomicU64::new(0),
+            sent_count: AtomicU64::new(0),
+            channel_counts: RwLock::new(HashMap::new()),
+            packet_counts: RwLock::new(HashMap::new()),
+            last_packet: RwLock::new(None),
+            reconnects: AtomicU64::new(0),
+            next_send: Mutex::new(Instant::now()),
+            sender: RwLock::new(sender),
             ui_update_sender,
+            read_only,
+        }
+    }
+
+    pub fn sender(&self) -> S {
+        self.sender.read().unwrap().clone()
+    }
+
+    /// Sends `packet` once its turn comes, keeping `SEND_INTERVAL` between all
+    /// packets no matter which task sends them.
+    async fn send<P>(&self, packet: P) -> nadylib::Result<()>
+    where
+        P: OutgoingPacket + Debug + Send + 'static,
+    {
+        let slot = {
+            let mut next_send = self.next_send.lock().unwrap();
+            let slot = (*next_send).max(Instant::now());
+            *next_send = slot + SEND_INTERVAL;
+            slot
+        };
+        sleep_until(slot).await;
+        self.sender().send(packet).await
+    }
+
+    /// Switches over to a freshly connected socket and drops state that the server will resend.
+    fn reset_session(&self, sender: S) {
+        *self.sender.write().unwrap() = sender;
+        self.logged_in.store(false, Ordering::Relaxed);
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+        self.channels
+            .write()
+            .unwrap()
+            .retain(|channel| matches!(channel, Channel::Tell(_)));
+
+        // Wake up pending lookups, they will not be answered on the new connection
+        for (_, notify) in self.pending_lookups.write().unwrap().drain() {
+            notify.notify_waiters();
+        }
+    }
+
+    fn count_packet(&self, packet: &ReceivedPacket) {
+        *self
+            .packet_counts
+            .write()
+            .unwrap()
+            .entry(packet_type(packet))
+            .or_insert(0) += 1;
+        *self.last_packet.write().unwrap() = Some(Local::now());
+    }
+
+    pub fn diagnostics(&self) -> Diagnostics {
+        let mut packet_counts: Vec<(&'static str, u64)> = self
+            .packet_counts
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, count)| (*name, *count))
+            .collect();
+        packet_counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+        Diagnostics {
+            packet_counts,
+            last_packet: *self.last_packet.read().unwrap(),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            queued: self.outbox.read().unwrap().len(),
+        }
+    }
+
+    /// Returns true and notifies the UI if outgoing packets are disabled.
+    fn refuse_read_only(&self) -> bool {
+        if self.read_only {
+            let _ = self.ui_update_sender.send(UiUpdate::Status(String::from(
+                "Read-only mode, nothing was sent",
+            )));
         }
+
+        self.read_only
+    }
+
+    pub fn resolved_channels(&self) -> Vec<ResolvedChannel> {
+        let channels = self.channels.read().unwrap().clone();
+        channels
+            .iter()
+            .map(|channel| ResolvedChannel::new(self, channel))
+            .collect()
+    }
+
+    /// The private channels invited to that were not joined since, oldest first.
+    pub fn resolved_invites(&self) -> Vec<ResolvedChannel> {
+        let invites = self.past_invites.read().unwrap().clone();
+        invites
+            .iter()
+            .map(|channel| ResolvedChannel::new(self, channel))
+            .collect()
+    }
+
+    pub fn clear_invites(&self) {
+        let count = self.past_invites.write().unwrap().drain(..).count();
+        let _ = self
+            .ui_update_sender
+            .send(UiUpdate::Status(format!("Forgot {} invites", count)));
     }
 
+    /// Resolves `user` to an id, asking the server if needed, and shows why it failed.
     pub async fn lookup_user(&self, user: String) -> Option<u32> {
+        if !command::is_valid_character_name(&user) {
+            let _ = self
+                .ui_update_sender
+                .send(UiUpdate::Status(format!("Invalid character name {}", user)));
+            return None;
+        }
+
+        let maybe_id = self.resolve_user(user.clone()).await;
+        if maybe_id.is_none() {
+            let _ = self
+                .ui_update_sender
+                .send(UiUpdate::Status(format!("Could not resolve {}", user)));
+        }
+        maybe_id
+    }
+
+    /// Like `lookup_user`, but leaves reporting failures to the caller.
+    async fn resolve_user(&self, user: String) -> Option<u32> {
+        if !command::is_valid_character_name(&user) {
+            return None;
+        }
+
         let maybe_user = self
             .user_lookup
             .read()
@@ -235,11 +915,14 @@ impl ChatState {
                 let pack = ClientLookupPacket {
                     character_name: user.clone(),
                 };
-                let _ = self.sender.send(pack).await;
+                let _ = self.send(pack).await;
                 notify
             };
 
-            notify.notified().await;
+            if timeout(LOOKUP_TIMEOUT, notify.notified()).await.is_err() {
+                // The server never answered, so a later lookup has to ask again
+                self.pending_lookups.write().unwrap().remove(&user);
+            }
 
             self.user_lookup
                 .read()
@@ -249,166 +932,1128 @@ impl ChatState {
         }
     }
 
-    pub async fn invite(&self, user: String) {
-        let user_id = self.lookup_user(user).await;
-
-        if let Some(id) = user_id {
-            let packet = OutPrivgrpInvitePacket { character_id: id };
-            let _ = self.sender.send(packet).await;
+    fn update_buddy_status(&self, character_id: u32, online: bool) {
+        let mut buddy_status = self.buddy_status.write().unwrap();
+        // Only record the time when the status actually changes
+        if buddy_status.get(&character_id).map(|(o, _)| *o) != Some(online) {
+            buddy_status.insert(character_id, (online, Local::now()));
         }
     }
 
-    pub async fn kick(&self, user: String) {
-        let user_id = self.lookup_user(user).await;
+    pub async fn seen(&self, user: String) {
+        let status = match self.lookup_user(user.clone()).await {
+            Some(id) => self.buddy_status.read().unwrap().get(&id).copied(),
+            None => None,
+        };
 
-        if let Some(id) = user_id {
-            let packet = OutPrivgrpKickPacket { character_id: id };
-            let _ = self.sender.send(packet).await;
-        }
+        let text = match status {
+            Some((true, since)) => format!(
+                "{} is online (since {})",
+                user,
+                since.format("%Y-%m-%d %H:%M")
+            ),
+            Some((false, since)) => {
+                format!("{} was last seen {}", user, since.format("%Y-%m-%d %H:%M"))
+            }
+            None => format!("No data for {}", user),
+        };
+        let _ = self.ui_update_sender.send(UiUpdate::System(text));
     }
 
-    pub async fn leave(&self, user: String) {
-        let user_id = self.lookup_user(user).await;
+    /// Dumps the configuration and connection state for bug reports, leaving out the password.
+    fn debug(&self, config: &Config) {
+        let character_id = self.current_user.load(Ordering::Relaxed);
+        let bell_on = [
+            (config.bell_on.tell, "tell"),
+            (config.bell_on.highlight, "highlight"),
+        ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, trigger)| *trigger)
+        .collect::<Vec<_>>();
 
-        if let Some(id) = user_id {
-            let packet = PrivgrpPartPacket {
-                channel: Channel::PrivateChannel(id),
-            };
-            let _ = self.sender.send(packet).await;
+        let text = [
+            format!(
+                "Server: {}{}",
+                config.server,
+                if config.direct_login {
+                    " (direct login)"
+                } else {
+                    ""
+                }
+            ),
+            format!("Profile: {}", config.profile),
+            format!("Account: {} (password redacted)", config.user_name),
+            format!("Character: {} ({})", config.character_name, character_id),
+            format!("Known channels: {}", self.channels.read().unwrap().len()),
+            format!("Known users: {}", self.user_lookup.read().unwrap().len()),
+            format!(
+                "Pending lookups: {}",
+                self.pending_lookups.read().unwrap().len()
+            ),
+            format!(
+                "Ignored users: {}",
+                self.ignored.read().unwrap().words().len()
+            ),
+            format!(
+                "Runtime highlights: {}",
+                self.highlights.read().unwrap().words().len()
+            ),
+            format!("Read-only: {}", if self.read_only { "yes" } else { "no" }),
+            format!(
+                "Bell on: {}",
+                if bell_on.is_empty() {
+                    String::from("nothing")
+                } else {
+                    bell_on.join(", ")
+                }
+            ),
+        ]
+        .join("\n");
+        let _ = self.ui_update_sender.send(UiUpdate::System(text));
+    }
+
+    /// Name of the logged-in character as the server knows it, if it was selected yet.
+    pub fn current_user_name(&self) -> Option<String> {
+        match self.current_user.load(Ordering::Relaxed) {
+            0 => None,
+            id => self.user_lookup.read().unwrap().get_by_left(&id).cloned(),
         }
     }
 
-    pub async fn send_tell(&self, user: String, text: String) {
-        let user_id = self.lookup_user(user).await;
+    fn whoami(&self, config: &Config) {
+        let id = self.current_user.load(Ordering::Relaxed);
+        let character = match self.current_user_name() {
+            Some(name) => format!("Character: {} ({})", name, id),
+            None if id == 0 => format!("Character: {} (not selected yet)", config.character_name),
+            None => format!(
+                "Character: {} ({}, name not known yet)",
+                config.character_name, id
+            ),
+        };
+        let text = [character, format!("Account: {}", config.user_name)].join("\n");
+        let _ = self.ui_update_sender.send(UiUpdate::System(text));
+    }
 
-        if let Some(id) = user_id {
-            let message = Message {
-                sender: Some(self.current_user.load(Ordering::Relaxed)),
-                channel: Channel::Tell(id),
-                text,
-                send_tag: String::from("\u{0}"),
-            };
-            let resolved = ResolvedMessage::new(self, &message);
-            let _ = self.ui_update_sender.send(UiUpdate::Message(resolved));
-            if !self.channels.read().unwrap().iter().any(|channel| {
-                if let Channel::Tell(user) = channel {
-                    *user == id
+    fn server(&self, config: &Config, latency: Option<Duration>) {
+        let text = [
+            format!(
+                "Dimension: {}",
+                config::dimension(&config.server).unwrap_or("unknown")
+            ),
+            format!("Server: {}", config.server),
+            format!(
+                "Logged in: {}",
+                if self.logged_in.load(Ordering::Relaxed) {
+                    "yes"
                 } else {
-                    false
+                    "no"
                 }
-            }) {
-                self.channels.write().unwrap().push(message.channel.clone());
-            }
-            let packet = MsgPrivatePacket { message };
-            let _ = self.sender.send(packet).await;
-        }
+            ),
+            format!(
+                "Latency: {}",
+                latency.map_or_else(
+                    || String::from("not measured yet"),
+                    |latency| format!("{}ms", latency.as_millis())
+                )
+            ),
+        ]
+        .join("\n");
+        let _ = self.ui_update_sender.send(UiUpdate::System(text));
     }
 
-    pub async fn send_message(&self, resolved_channel: ResolvedChannel, text: String) {
-        let channel = match resolved_channel.r#type {
-            ChannelType::Vicinity => Channel::Vicinity,
-            ChannelType::Tell => Channel::Tell(resolved_channel.id),
-            ChannelType::PrivateChannel => Channel::PrivateChannel(resolved_channel.id),
-            ChannelType::Group => self
-                .channels
-                .read()
-                .unwrap()
-                .iter()
-                .find(|c| {
-                    if let Channel::Group(g) = c {
-                        resolved_channel.id == g.id
-                    } else {
-                        false
-                    }
-                })
-                .unwrap()
-                .clone(),
+    /// Adds or removes `word` from `list`, reporting if the list could not be saved.
+    /// Returns whether the list changed.
+    fn edit_list(&self, list: &RwLock<WordList>, word: &str, add: bool) -> bool {
+        let mut list = list.write().unwrap();
+        let result = if add {
+            list.add(word)
+        } else {
+            list.remove(word)
         };
 
-        let message = Message {
-            sender: Some(self.current_user.load(Ordering::Relaxed)),
-            channel,
-            text,
-            send_tag: String::from("\u{0}"),
+        result.unwrap_or_else(|e| {
+            let _ = self.ui_update_sender.send(UiUpdate::Status(format!(
+                "Failed to save {}: {}",
+                list.path().display(),
+                e
+            )));
+            true
+        })
+    }
+
+    pub fn ignore(&self, user: &str, add: bool) {
+        let changed = self.edit_list(&self.ignored, user, add);
+        let text = match (add, changed) {
+            (true, true) => format!("Ignoring {}", user),
+            (true, false) => format!("{} is already ignored", user),
+            (false, true) => format!("No longer ignoring {}", user),
+            (false, false) => format!("{} is not ignored", user),
         };
+        let _ = self.ui_update_sender.send(UiUpdate::Status(text));
+    }
 
-        match message.channel {
-            Channel::Group(_) => self
-                .sender
-                .send(GroupMessagePacket { message })
-                .await
-                .unwrap(),
-            Channel::Tell(_) => {
-                let resolved = ResolvedMessage::new(self, &message);
-                let _ = self.ui_update_sender.send(UiUpdate::Message(resolved));
-                self.sender
-                    .send(MsgPrivatePacket { message })
-                    .await
-                    .unwrap()
+    /// Removes the tell conversation with `user` from the channel list.
+    pub fn close(&self, user: &str) {
+        let id = self
+            .user_lookup
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(user))
+            .map(|(id, _)| *id);
+
+        let mut channels = self.channels.write().unwrap();
+        let position = id.and_then(|id| channels.iter().position(|c| *c == Channel::Tell(id)));
+        if let Some(position) = position {
+            let channel = channels.remove(position);
+            drop(channels);
+            let _ = self
+                .ui_update_sender
+                .send(UiUpdate::Closed(ResolvedChannel::new(self, &channel)));
+            let _ = self
+                .ui_update_sender
+                .send(UiUpdate::Channels(self.resolved_channels()));
+        } else {
+            let _ = self
+                .ui_update_sender
+                .send(UiUpdate::Status(format!("No conversation with {}", user)));
+        }
+    }
+
+    /// Mutes or unmutes the channel called `name`, with or without its type prefix.
+    pub fn mute(&self, name: &str, add: bool) {
+        let channel = self
+            .resolved_channels()
+            .into_iter()
+            .chain(once(ResolvedChannel::vicinity(&self.prefixes)))
+            .find(|c| c.is_named(name));
+
+        if let Some(channel) = channel {
+            let changed = {
+                let mut muted = self.muted.write().unwrap();
+                if add {
+                    muted.insert(channel.key())
+                } else {
+                    muted.remove(&channel.key())
+                }
+            };
+            let text = match (add, changed) {
+                (true, true) => format!("Muted {}", channel.render()),
+                (true, false) => format!("{} is already muted", channel.render()),
+                (false, true) => format!("Unmuted {}", channel.render()),
+                (false, false) => format!("{} is not muted", channel.render()),
+            };
+            if changed {
+                let muted = self.muted.read().unwrap().iter().copied().collect();
+                let _ = self.ui_update_sender.send(UiUpdate::Muted(muted));
             }
-            Channel::PrivateChannel(_) => self
-                .sender
-                .send(PrivgrpMessagePacket { message })
-                .await
-                .unwrap(),
-            Channel::Vicinity => panic!("impossible"),
+            let _ = self.ui_update_sender.send(UiUpdate::Status(text));
+        } else {
+            let _ = self
+                .ui_update_sender
+                .send(UiUpdate::Status(format!("No channel named {}", name)));
         }
     }
-}
 
-pub async fn chat_task(
-    mut sock: AOSocket,
-    mut state_query_receiver: UnboundedReceiver<StateQuery>,
-    mut command_receiver: UnboundedReceiver<Command>,
-    ui_update_sender: UnboundedSender<UiUpdate>,
-    username: String,
-    char_name: String,
-    password: String,
-) -> nadylib::Result<()> {
-    let chat_state = Arc::new(ChatState::new(sock.get_sender(), ui_update_sender.clone()));
+    /// Sets or, with `None`, removes the local display name of a channel.
+    pub fn alias_channel(&self, name: &str, alias: Option<String>) {
+        let channel = self
+            .resolved_channels()
+            .into_iter()
+            .find(|c| c.is_named(name));
 
-    loop {
-        tokio::select! {
-            packet = sock.read_packet() => {
-                if let Ok(packet) = packet {
-                    match packet {
-                        ReceivedPacket::LoginSeed(s) => {
-                            sock.login(&username, &password, &s.login_seed).await?;
-                        }
-                        ReceivedPacket::LoginCharlist(c) => {
-                            let character = c.characters.iter().find(|i| i.name == char_name).unwrap();
-                            let pack = LoginSelectPacket {
-                                character_id: character.id,
-                            };
-                            chat_state.current_user.store(character.id, Ordering::Relaxed);
-                            sock.send(pack).await?;
-                        }
-                        ReceivedPacket::LoginError(e) => panic!("{}", e.message),
-                        ReceivedPacket::ClientName(c) => {
-                            chat_state
-                                .user_lookup
-                                .write()
-                                .unwrap()
-                                .insert(c.character_id, c.character_name);
+        if let Some(channel) = channel {
+            let real_name = channel.real_name();
+            {
+                let mut aliases = self.channel_aliases.write().unwrap();
+                if let Err(e) = aliases.set(&real_name, alias.as_deref()) {
+                    let _ = self.ui_update_sender.send(UiUpdate::Status(format!(
+                        "Failed to save {}: {}",
+                        aliases.path().display(),
+                        e
+                    )));
+                }
+            }
+
+            let text = match alias {
+                Some(alias) => format!("{} is now shown as {}", real_name, alias),
+                None => format!("Removed the alias of {}", real_name),
+            };
+            let _ = self.ui_update_sender.send(UiUpdate::System(text));
+            let _ = self
+                .ui_update_sender
+                .send(UiUpdate::Channels(self.resolved_channels()));
+        } else {
+            let _ = self
+                .ui_update_sender
+                .send(UiUpdate::Status(format!("No channel named {}", name)));
+        }
+    }
+
+    pub fn highlight(&self, word: &str, add: bool) {
+        let changed = self.edit_list(&self.highlights, word, add);
+        let text = match (add, changed) {
+            (true, true) => format!("Highlighting {}", word),
+            (true, false) => format!("{} is already highlighted", word),
+            (false, true) => format!("No longer highlighting {}", word),
+            (false, false) => format!("{} is not highlighted", word),
+        };
+        if changed {
+            let words = self.highlights.read().unwrap().words().to_vec();
+            let _ = self.ui_update_sender.send(UiUpdate::Highlights(words));
+        }
+        let _ = self.ui_update_sender.send(UiUpdate::Status(text));
+    }
+
+    fn stats(&self) {
+        let uptime = self.started.elapsed().as_secs();
+        let mut channel_counts: Vec<(String, u64)> = self
+            .channel_counts
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(channel, count)| (channel.clone(), *count))
+            .collect();
+        channel_counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        let mut lines = vec![
+            format!(
+                "Uptime: {}:{:02}:{:02}",
+                uptime / 3600,
+                uptime / 60 % 60,
+                uptime % 60
+            ),
+            format!(
+                "Messages received: {}",
+                self.received_count.load(Ordering::Relaxed)
+            ),
+            format!("Messages sent: {}", self.sent_count.load(Ordering::Relaxed)),
+        ];
+        lines.extend(
+            channel_counts
+                .iter()
+                .map(|(channel, count)| format!("  {}: {}", channel, count)),
+        );
+        let text = lines.join("\n");
+        let _ = self.ui_update_sender.send(UiUpdate::System(text));
+    }
+
+    fn user_name(&self, id: u32) -> String {
+        self.user_lookup
+            .read()
+            .unwrap()
+            .get_by_left(&id)
+            .cloned()
+            .unwrap_or_else(|| id.to_string())
+    }
+
+    async fn resolve_recipient(&self, recipient: Recipient) -> Option<u32> {
+        match recipient {
+            Recipient::Name(user) => self.lookup_user(user).await,
+            Recipient::LastSender(channel) => {
+                let last_sender = self
+                    .last_senders
+                    .read()
+                    .unwrap()
+                    .get(&channel.key())
+                    .copied();
+                if last_sender.is_none() {
+                    let _ = self.ui_update_sender.send(UiUpdate::Status(format!(
+                        "Nobody has sent anything in {} yet",
+                        channel.render()
+                    )));
+                }
+                last_sender
+            }
+        }
+    }
+
+    pub async fn invite(&self, recipient: Recipient) {
+        if self.refuse_read_only() {
+            return;
+        }
+
+        let user_id = self.resolve_recipient(recipient).await;
+
+        if let Some(id) = user_id {
+            let packet = OutPrivgrpInvitePacket { character_id: id };
+            let _ = self.send(packet).await;
+        }
+    }
+
+    /// Invites every user, pausing between invites, then reports who was invited
+    /// and who could not be.
+    pub async fn invite_all(&self, users: Vec<String>) {
+        if self.refuse_read_only() {
+            return;
+        }
+
+        let mut invited = Vec::new();
+        let mut failed = Vec::new();
+        for user in users {
+            let sent = match self.lookup_user(user.clone()).await {
+                Some(id) => self
+                    .send(OutPrivgrpInvitePacket { character_id: id })
+                    .await
+                    .is_ok(),
+                None => false,
+            };
+            if sent {
+                invited.push(user);
+            } else {
+                failed.push(user);
+            }
+        }
+
+        self.report_outcome(&[("Invited", &invited), ("Could not invite", &failed)]);
+    }
+
+    /// Sends the lines of the file at `path` to a channel, refusing files above
+    /// `SEND_FILE_LIMIT`.
+    pub async fn send_file(&self, name: &str, path: PathBuf) {
+        if self.refuse_read_only() {
+            return;
+        }
+
+        let channel = self
+            .resolved_channels()
+            .into_iter()
+            .find(|c| c.is_named(name));
+        let contents = match metadata(&path) {
+            Ok(meta) if meta.len() > SEND_FILE_LIMIT => {
+                Err(format!("larger than {} KiB", SEND_FILE_LIMIT / 1024))
+            }
+            _ => read_to_string(&path).map_err(|e| e.to_string()),
+        };
+
+        let status = match (channel, contents) {
+            (None, _) => format!("No channel named {}", name),
+            (_, Err(e)) => format!("Could not send {}: {}", path.display(), e),
+            (Some(channel), Ok(contents)) => {
+                self.send_lines(&channel, &path, &contents).await;
+                return;
+            }
+        };
+        let _ = self.ui_update_sender.send(UiUpdate::Status(status));
+    }
+
+    /// Sends each line of `contents` to `channel`, pausing between lines, then reports
+    /// how many went out and which could not be sent. Empty lines are skipped.
+    async fn send_lines(&self, channel: &ResolvedChannel, path: &Path, contents: &str) {
+        let lines: Vec<(usize, &str)> = contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .collect();
+        let mut sent = 0;
+        let mut queued = 0;
+        let mut failed = Vec::new();
+        for (i, (number, line)) in lines.iter().enumerate() {
+            let _ = self.ui_update_sender.send(UiUpdate::Status(format!(
+                "Sending {}: line {} of {}",
+                path.display(),
+                i + 1,
+                lines.len()
+            )));
+            if self.decoration.apply(line).len() > MESSAGE_BYTE_LIMIT {
+                failed.push(format!("{} (too long)", number + 1));
+            } else {
+                match self.send_message(channel.clone(), line.to_string()).await {
+                    SendOutcome::Sent => sent += 1,
+                    SendOutcome::Queued => queued += 1,
+                    SendOutcome::Failed => failed.push((number + 1).to_string()),
+                }
+            }
+        }
+
+        let mut report = vec![format!(
+            "Sent {} lines of {} to {}",
+            sent,
+            path.display(),
+            channel.render()
+        )];
+        if queued > 0 {
+            report.push(format!("Queued {} lines until logged in", queued));
+        }
+        if !failed.is_empty() {
+            report.push(format!("Could not send line {}", failed.join(", ")));
+        }
+        let _ = self
+            .ui_update_sender
+            .send(UiUpdate::System(report.join("\n")));
+    }
+
+    /// Invites the characters in the file at `path`, separated by commas or whitespace.
+    pub async fn invite_file(&self, path: PathBuf) {
+        match read_to_string(&path) {
+            Ok(contents) => {
+                let users = contents
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                self.invite_all(users).await;
+            }
+            Err(e) => {
+                let _ = self.ui_update_sender.send(UiUpdate::Status(format!(
+                    "Could not read {}: {}",
+                    path.display(),
+                    e
+                )));
+            }
+        }
+    }
+
+    /// Reports which names an action over several characters or channels worked for,
+    /// was held back for or failed for, as one system message.
+    fn report_outcome(&self, outcomes: &[(&str, &[String])]) {
+        let lines: Vec<String> = outcomes
+            .iter()
+            .filter(|(_, names)| !names.is_empty())
+            .map(|(prefix, names)| format!("{} {}", prefix, names.join(", ")))
+            .collect();
+        let _ = self
+            .ui_update_sender
+            .send(UiUpdate::System(lines.join("\n")));
+    }
+
+    pub async fn kick(&self, recipient: Recipient) {
+        if self.refuse_read_only() {
+            return;
+        }
+
+        let user_id = self.resolve_recipient(recipient).await;
+
+        if let Some(id) = user_id {
+            let packet = OutPrivgrpKickPacket { character_id: id };
+            if self.send(packet).await.is_ok() {
+                let own_channel =
+                    Channel::PrivateChannel(self.current_user.load(Ordering::Relaxed));
+                let _ = self.ui_update_sender.send(UiUpdate::Kick(
+                    self.user_name(id),
+                    ResolvedChannel::new(self, &own_channel),
+                ));
+            }
+        }
+    }
+
+    /// Sends the join packet for the private channel of `id`. Returns whether it was sent.
+    async fn send_join(&self, id: u32) -> bool {
+        let channel = Channel::PrivateChannel(id);
+        let packet = PrivgrpJoinPacket {
+            channel: channel.clone(),
+        };
+        if self.send(packet).await.is_err() {
+            return false;
+        }
+
+        // Accepting an invite makes it stale
+        self.past_invites.write().unwrap().retain(|c| *c != channel);
+        let mut channels = self.channels.write().unwrap();
+        if !channels.contains(&channel) {
+            channels.push(channel);
+        }
+        true
+    }
+
+    /// Joins a private channel by its owner's id, without looking up a name.
+    pub async fn join_id(&self, id: u32) {
+        if self.refuse_read_only() {
+            return;
+        }
+
+        if self.send_join(id).await {
+            let _ = self
+                .ui_update_sender
+                .send(UiUpdate::Status(format!("Joining private channel {}", id)));
+        }
+    }
+
+    /// Joins a private channel by its owner's name. Returns whether the join was sent.
+    pub async fn join(&self, user: String) -> bool {
+        if self.refuse_read_only() {
+            return false;
+        }
+
+        match self.lookup_user(user.clone()).await {
+            Some(id) if self.send_join(id).await => {
+                let _ = self.ui_update_sender.send(UiUpdate::Status(format!(
+                    "Joining private channel {}",
+                    user
+                )));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Joins the private channels of `owners` one after another, then reports which
+    /// joins were sent and which owners could not be resolved.
+    pub async fn autojoin(&self, owners: Vec<String>) {
+        let mut joined = Vec::new();
+        let mut failed = Vec::new();
+        for owner in owners {
+            if self.join(owner.clone()).await {
+                joined.push(owner);
+            } else {
+                failed.push(owner);
+            }
+        }
+
+        self.report_outcome(&[("Auto-joining", &joined), ("Could not auto-join", &failed)]);
+    }
+
+    pub async fn leave(&self, user: String) {
+        if self.refuse_read_only() {
+            return;
+        }
+
+        let user_id = self.lookup_user(user).await;
+
+        if let Some(id) = user_id {
+            let channel = Channel::PrivateChannel(id);
+            let packet = PrivgrpPartPacket {
+                channel: channel.clone(),
+            };
+            if self.send(packet).await.is_ok() {
+                self.channels.write().unwrap().retain(|c| *c != channel);
+                let _ = self
+                    .ui_update_sender
+                    .send(UiUpdate::Leave(ResolvedChannel::new(self, &channel)));
+            }
+        }
+    }
+
+    /// Sends the same tell to every user, echoing it once with all recipients.
+    pub async fn send_tell(&self, users: Vec<String>, text: String) {
+        if self.refuse_read_only() {
+            return;
+        }
+        if !self.logged_in.load(Ordering::Relaxed) {
+            self.queue(Queued::Tell(users, text));
+            return;
+        }
+        let text = self.decoration.apply(&text);
+
+        let mut recipients = Vec::new();
+        let mut unresolved = Vec::new();
+        for user in users {
+            // Unresolved names are reported together below
+            match self.resolve_user(user.clone()).await {
+                Some(id) => recipients.push(id),
+                None => unresolved.push(user),
+            }
+        }
+        if !unresolved.is_empty() {
+            let _ = self.ui_update_sender.send(UiUpdate::Status(format!(
+                "Could not resolve {}",
+                unresolved.join(", ")
+            )));
+        }
+
+        let messages: Vec<Message> = recipients
+            .iter()
+            .map(|id| Message {
+                sender: Some(self.current_user.load(Ordering::Relaxed)),
+                channel: Channel::Tell(*id),
+                text: text.clone(),
+                send_tag: self.send_tag.clone(),
+            })
+            .collect();
+        if messages.is_empty() {
+            return;
+        }
+
+        let mut echo = ResolvedMessage::new(self, &messages[0]);
+        let single_channel = echo.channel.clone();
+        if recipients.len() > 1 {
+            echo.channel.name = recipients
+                .iter()
+                .map(|id| self.user_name(*id))
+                .collect::<Vec<_>>()
+                .join(", ");
+        }
+        let echo_id = self.emit_echo(echo);
+
+        for id in &recipients {
+            if !self.channels.read().unwrap().iter().any(|channel| {
+                if let Channel::Tell(user) = channel {
+                    user == id
+                } else {
+                    false
+                }
+            }) {
+                self.channels.write().unwrap().push(Channel::Tell(*id));
+            }
+        }
+
+        let mut sent = true;
+        for message in messages {
+            let packet = MsgPrivatePacket { message };
+            let result = self.send(packet).await;
+            self.report_send_result(&result);
+            sent &= result.is_ok();
+        }
+        self.finish_echo(echo_id, sent);
+
+        if sent && recipients.len() == 1 {
+            let _ = self
+                .ui_update_sender
+                .send(UiUpdate::TellSent(single_channel));
+        }
+    }
+
+    /// Sends `text` to a channel, or queues it while not logged in.
+    pub async fn send_message(
+        &self,
+        resolved_channel: ResolvedChannel,
+        text: String,
+    ) -> SendOutcome {
+        if self.refuse_read_only() {
+            return SendOutcome::Failed;
+        }
+        if !self.logged_in.load(Ordering::Relaxed) {
+            self.queue(Queued::Message(resolved_channel, text));
+            return SendOutcome::Queued;
+        }
+        let text = self.decoration.apply(&text);
+
+        let channel = match resolved_channel.r#type {
+            ChannelType::Vicinity | ChannelType::AnonymousVicinity => Channel::Vicinity,
+            ChannelType::Tell => Channel::Tell(resolved_channel.id),
+            ChannelType::PrivateChannel => Channel::PrivateChannel(resolved_channel.id),
+            ChannelType::Group | ChannelType::Org => {
+                let group = self
+                    .channels
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .find(|c| {
+                        if let Channel::Group(g) = c {
+                            resolved_channel.id == g.id
+                        } else {
+                            false
                         }
-                        ReceivedPacket::MsgVicinity(m) => {
-                            let resolved = ResolvedMessage::new(&chat_state, &m.message);
-                            let _ = ui_update_sender.send(UiUpdate::Message(resolved));
+                    })
+                    .cloned();
+                if let Some(group) = group {
+                    group
+                } else {
+                    let _ = self.ui_update_sender.send(UiUpdate::Status(format!(
+                        "Could not send: channel {} is gone",
+                        resolved_channel.name
+                    )));
+                    return SendOutcome::Failed;
+                }
+            }
+        };
+
+        let message = Message {
+            sender: Some(self.current_user.load(Ordering::Relaxed)),
+            channel,
+            text,
+            send_tag: self.send_tag.clone(),
+        };
+
+        let result = match message.channel {
+            Channel::Group(_) => self.send(GroupMessagePacket { message }).await,
+            Channel::Tell(_) => {
+                let echo_id = self.emit_echo(ResolvedMessage::new(self, &message));
+                let result = self.send(MsgPrivatePacket { message }).await;
+                self.finish_echo(echo_id, result.is_ok());
+                result
+            }
+            Channel::PrivateChannel(_) => self.send(PrivgrpMessagePacket { message }).await,
+            Channel::Vicinity => panic!("impossible"),
+        };
+        self.report_send_result(&result);
+        if result.is_ok() {
+            SendOutcome::Sent
+        } else {
+            SendOutcome::Failed
+        }
+    }
+
+    /// Sends `text` to every private channel the client is in, pausing between
+    /// channels, then reports where it went and where it could not be sent.
+    pub async fn broadcast(&self, text: String) {
+        if self.refuse_read_only() {
+            return;
+        }
+
+        let channels: Vec<ResolvedChannel> = self
+            .resolved_channels()
+            .into_iter()
+            .filter(|channel| channel.r#type == ChannelType::PrivateChannel)
+            .collect();
+        if channels.is_empty() {
+            let _ = self
+                .ui_update_sender
+                .send(UiUpdate::System(String::from("Not in any private channel")));
+            return;
+        }
+
+        let mut sent = Vec::new();
+        let mut queued = Vec::new();
+        let mut failed = Vec::new();
+        for channel in channels {
+            let name = channel.render();
+            match self.send_message(channel, text.clone()).await {
+                SendOutcome::Sent => sent.push(name),
+                SendOutcome::Queued => queued.push(name),
+                SendOutcome::Failed => failed.push(name),
+            }
+        }
+
+        self.report_outcome(&[
+            ("Broadcast to", &sent),
+            ("Queued until logged in for", &queued),
+            ("Could not send to", &failed),
+        ]);
+    }
+
+    /// Holds `queued` back until the login completes.
+    fn queue(&self, queued: Queued) {
+        let text = format!("Not connected, queued {} (pending)", queued.describe());
+        self.outbox.write().unwrap().push_back(queued);
+        let _ = self.ui_update_sender.send(UiUpdate::System(text));
+    }
+
+    /// Lists what is waiting in the outbox, or drops all of it if `clear`.
+    pub fn outbox(&self, clear: bool) {
+        let mut outbox = self.outbox.write().unwrap();
+        let update = if clear {
+            let count = outbox.drain(..).count();
+            UiUpdate::Status(format!("Cancelled {} queued messages", count))
+        } else if outbox.is_empty() {
+            UiUpdate::Status(String::from("Nothing is queued"))
+        } else {
+            let lines: Vec<String> = once(format!("{} queued messages:", outbox.len()))
+                .chain(outbox.iter().map(Queued::describe))
+                .collect();
+            UiUpdate::System(lines.join("\n"))
+        };
+        let _ = self.ui_update_sender.send(update);
+    }
+
+    /// Sends everything queued while not logged in, pausing between messages.
+    async fn flush_outbox(&self) {
+        let queued: Vec<Queued> = self.outbox.write().unwrap().drain(..).collect();
+        if queued.is_empty() {
+            return;
+        }
+
+        let _ = self.ui_update_sender.send(UiUpdate::Status(format!(
+            "Sending {} queued messages",
+            queued.len()
+        )));
+        for queued in queued {
+            // Anything that doesn't go out because the connection dropped again is queued again
+            match queued {
+                Queued::Message(channel, text) => {
+                    self.send_message(channel, text).await;
+                }
+                Queued::Tell(users, text) => self.send_tell(users, text).await,
+            }
+        }
+    }
+
+    fn report_send_result(&self, result: &nadylib::Result<()>) {
+        if result.is_ok() {
+            self.sent_count.fetch_add(1, Ordering::Relaxed);
+        } else {
+            let _ = self.ui_update_sender.send(UiUpdate::Status(String::from(
+                "Failed to send message (not connected)",
+            )));
+        }
+    }
+
+    pub async fn send_org_message(&self, text: String) {
+        let org = self
+            .channels
+            .read()
+            .unwrap()
+            .iter()
+            .find(|c| {
+                if let Channel::Group(g) = c {
+                    g.r#type == GroupType::Org
+                } else {
+                    false
+                }
+            })
+            .cloned();
+
+        if let Some(channel) = org {
+            let resolved = ResolvedChannel::new(self, &channel);
+            self.send_message(resolved, text).await;
+        } else {
+            let _ = self
+                .ui_update_sender
+                .send(UiUpdate::Status(String::from("You are not in an org")));
+        }
+    }
+
+    fn emit_message(&self, resolved: ResolvedMessage, sender: Option<u32>) {
+        if let (Some(name), false) = (&resolved.sender, resolved.outgoing) {
+            if self.ignored.read().unwrap().contains(name) {
+                return;
+            }
+        }
+        if !resolved.outgoing && self.muted.read().unwrap().contains(&resolved.channel.key()) {
+            return;
+        }
+        if !resolved.outgoing {
+            self.received_count.fetch_add(1, Ordering::Relaxed);
+            *self
+                .channel_counts
+                .write()
+                .unwrap()
+                .entry(resolved.channel.render())
+                .or_insert(0) += 1;
+        }
+        if let (Some(sender), false) = (sender, resolved.outgoing) {
+            self.last_senders
+                .write()
+                .unwrap()
+                .insert(resolved.channel.key(), sender);
+        }
+        let _ = self.ui_update_sender.send(UiUpdate::Message(resolved));
+    }
+
+    /// Answers a tell from `user` with an auto-response. The echo is marked, so it
+    /// can't be mistaken for something that was typed.
+    pub async fn auto_respond(&self, user: u32, response: String) {
+        if self.read_only || self.ignored.read().unwrap().contains(&self.user_name(user)) {
+            return;
+        }
+
+        let message = |text: String| Message {
+            sender: Some(self.current_user.load(Ordering::Relaxed)),
+            channel: Channel::Tell(user),
+            text,
+            send_tag: self.send_tag.clone(),
+        };
+        let echo = message(format!("[auto-response] {}", response));
+        let echo_id = self.emit_echo(ResolvedMessage::new(self, &echo));
+        let message = message(response);
+        let result = self.send(MsgPrivatePacket { message }).await;
+        self.finish_echo(echo_id, result.is_ok());
+        self.report_send_result(&result);
+    }
+
+    /// Echoes an outgoing message that the server will not send back, marked as pending.
+    fn emit_echo(&self, mut resolved: ResolvedMessage) -> u64 {
+        let id = self.next_echo_id.fetch_add(1, Ordering::Relaxed);
+        resolved.delivery = Some((id, Delivery::Pending));
+        let _ = self.ui_update_sender.send(UiUpdate::Message(resolved));
+        id
+    }
+
+    fn finish_echo(&self, id: u64, sent: bool) {
+        let delivery = if sent {
+            Delivery::Sent
+        } else {
+            Delivery::Failed
+        };
+        let _ = self.ui_update_sender.send(UiUpdate::Delivery(id, delivery));
+    }
+
+    fn emit_vicinity_message(&self, message: &Message, anonymous: bool) {
+        let mut resolved = ResolvedMessage::new(self, message);
+        if anonymous {
+            resolved.channel.r#type = ChannelType::AnonymousVicinity;
+        }
+        self.emit_message(resolved, message.sender);
+    }
+}
+
+async fn connect<C: Connector>(
+    connector: &C,
+    ui_update_sender: &UnboundedSender<UiUpdate>,
+    config: &Config,
+    state: ConnectionState,
+) -> C::Socket {
+    let _ = ui_update_sender.send(UiUpdate::Connection(state));
+
+    loop {
+        let _ = ui_update_sender.send(UiUpdate::Status(format!("Connecting to {}", config.server)));
+
+        if let Some(mut sock) = connector.connect(&config.server).await {
+            // Test servers without the seed handshake expect the login right away
+            if !config.direct_login
+                || sock
+                    .login(&config.user_name, &config.password, "")
+                    .await
+                    .is_ok()
+            {
+                return sock;
+            }
+        }
+
+        let _ = ui_update_sender.send(UiUpdate::Status(String::from(
+            "Connection failed, retrying",
+        )));
+        sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn reconnect<C: Connector>(
+    connector: &C,
+    chat_state: &ChatState<<C::Socket as PacketSocket>::Sender>,
+    config: &Config,
+) -> C::Socket {
+    let sock = connect(
+        connector,
+        &chat_state.ui_update_sender,
+        config,
+        ConnectionState::Reconnecting,
+    )
+    .await;
+    chat_state.reset_session(sock.sender());
+    sock
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn chat_task<C: Connector>(
+    connector: C,
+    mut state_query_receiver: UnboundedReceiver<StateQuery>,
+    mut command_receiver: UnboundedReceiver<Command>,
+    ui_update_sender: UnboundedSender<UiUpdate>,
+    config: Config,
+    read_only: bool,
+    ignored: WordList,
+    highlights: WordList,
+    channel_aliases: ChannelAliases,
+) -> nadylib::Result<()> {
+    let mut sock = connect(
+        &connector,
+        &ui_update_sender,
+        &config,
+        ConnectionState::Connecting,
+    )
+    .await;
+    let chat_state = Arc::new(ChatState::new(
+        sock.sender(),
+        ui_update_sender.clone(),
+        read_only,
+        ignored,
+        highlights,
+        channel_aliases,
+        config.send_tag.clone(),
+        config.decoration.clone(),
+        config.prefixes.clone(),
+    ));
+    let mut ping_interval =
+        interval_at(Instant::now() + config.ping_interval, config.ping_interval);
+    let mut ping_sent: Option<Instant> = None;
+    let mut latency: Option<Duration> = None;
+    let mut logged_in_before = false;
+    let mut logged_in_at: Option<Instant> = None;
+    let mut quick_drops = 0;
+    let mut auto_responses =
+        config
+            .auto_responses
+            .as_ref()
+            .and_then(|path| match AutoResponses::load(path) {
+                Ok(responses) => {
+                    let _ = ui_update_sender.send(UiUpdate::System(format!(
+                        "Auto-responses are on, {} rules from {}",
+                        responses.rule_count(),
+                        path.display()
+                    )));
+                    Some(responses)
+                }
+                Err(e) => {
+                    let _ = ui_update_sender.send(UiUpdate::System(format!(
+                        "Could not read auto-responses {}: {}",
+                        path.display(),
+                        e
+                    )));
+                    None
+                }
+            });
+    let packet_log = config
+        .packet_log
+        .as_ref()
+        .and_then(|path| match PacketLog::open(path) {
+            Ok(log) => Some(log),
+            Err(e) => {
+                let _ = ui_update_sender.send(UiUpdate::System(format!(
+                    "Could not open packet log {}: {}",
+                    path.display(),
+                    e
+                )));
+                None
+            }
+        });
+
+    loop {
+        tokio::select! {
+            packet = sock.read_packet() => {
+                if let Some(packet) = packet {
+                    if let Some(log) = &packet_log {
+                        log.log(&packet);
+                    }
+                    chat_state.count_packet(&packet);
+                    match packet {
+                        ReceivedPacket::LoginSeed(s) => {
+                            let _ = ui_update_sender.send(UiUpdate::Connection(ConnectionState::Authenticating));
+                            let _ = ui_update_sender.send(UiUpdate::Status(String::from("Logging in")));
+                            sock.login(&config.user_name, &config.password, &s.login_seed).await?;
+                        }
+                        ReceivedPacket::LoginCharlist(c) => {
+                            let character = c.characters.iter().find(|i| i.name == config.character_name);
+                            if let Some(character) = character {
+                                let pack = LoginSelectPacket {
+                                    character_id: character.id,
+                                };
+                                chat_state.current_user.store(character.id, Ordering::Relaxed);
+                                sock.sender().send(pack).await?;
+                            } else {
+                                let reason = if c.characters.is_empty() {
+                                    String::from("Account has no characters")
+                                } else {
+                                    let names: Vec<&str> = c.characters.iter().map(|i| i.name.as_str()).collect();
+                                    format!(
+                                        "Character {} not found, the account has: {}",
+                                        config.character_name,
+                                        names.join(", ")
+                                    )
+                                };
+                                let _ = ui_update_sender.send(UiUpdate::Fatal(reason));
+                                return Ok(());
+                            }
                         }
-                        ReceivedPacket::MsgVicinitya(m) => {
-                            let resolved = ResolvedMessage::new(&chat_state, &m.message);
-                            let _ = ui_update_sender.send(UiUpdate::Message(resolved));
+                        ReceivedPacket::LoginError(e) => {
+                            let _ = ui_update_sender.send(UiUpdate::Fatal(format!("Login failed: {}", e.message)));
+                            return Ok(());
+                        }
+                        ReceivedPacket::ClientName(c) => {
+                            chat_state
+                                .user_lookup
+                                .write()
+                                .unwrap()
+                                .insert(c.character_id, c.character_name);
                         }
+                        ReceivedPacket::MsgVicinity(m) => chat_state.emit_vicinity_message(&m.message, false),
+                        ReceivedPacket::MsgVicinitya(m) => chat_state.emit_vicinity_message(&m.message, true),
                         ReceivedPacket::GroupAnnounce(g) => {
                             chat_state.channels.write().unwrap().push(g.channel);
+                            let _ = ui_update_sender.send(UiUpdate::Channels(chat_state.resolved_channels()));
                         }
                         ReceivedPacket::GroupMessage(m) => {
-                            let resolved = ResolvedMessage::new(&chat_state, &m.message);
-                            let _ = ui_update_sender.send(UiUpdate::Message(resolved));
+                            chat_state.emit_message(ResolvedMessage::new(&chat_state, &m.message), m.message.sender);
                         }
                         ReceivedPacket::MsgPrivate(m) => {
-                            let resolved = ResolvedMessage::new(&chat_state, &m.message);
-                            let _ = ui_update_sender.send(UiUpdate::Message(resolved));
+                            chat_state.emit_message(ResolvedMessage::new(&chat_state, &m.message), m.message.sender);
+                            if let (Some(responses), Channel::Tell(user)) = (&mut auto_responses, &m.message.channel) {
+                                if let Some(response) = responses.respond(*user, &m.message.text) {
+                                    let chat_state = chat_state.clone();
+                                    let user = *user;
+                                    tokio::spawn(async move { chat_state.auto_respond(user, response).await });
+                                }
+                            }
                             if !chat_state.channels.read().unwrap().iter().any(|channel| {
                                 if let Channel::Tell(user) = channel {
                                     if let Channel::Tell(other_user) = m.message.channel {
@@ -424,11 +2069,19 @@ pub async fn chat_task(
                             }
                         }
                         ReceivedPacket::PrivgrpInvite(p) => {
-                            chat_state.past_invites.write().unwrap().push(p.channel);
+                            let mut past_invites = chat_state.past_invites.write().unwrap();
+                            // A repeated invite moves to the end, as the newest
+                            past_invites.retain(|c| *c != p.channel);
+                            past_invites.push(p.channel);
+                        }
+                        ReceivedPacket::PrivgrpKick(k) => {
+                            let resolved = ResolvedChannel::new(&chat_state, &k.channel);
+                            chat_state.channels.write().unwrap().retain(|c| *c != k.channel);
+                            let _ = ui_update_sender.send(UiUpdate::Kicked(resolved));
+                            let _ = ui_update_sender.send(UiUpdate::Channels(chat_state.resolved_channels()));
                         }
                         ReceivedPacket::PrivgrpMessage(m) => {
-                            let resolved = ResolvedMessage::new(&chat_state, &m.message);
-                            let _ = ui_update_sender.send(UiUpdate::Message(resolved));
+                            chat_state.emit_message(ResolvedMessage::new(&chat_state, &m.message), m.message.sender);
                         }
                         ReceivedPacket::ClientLookup(c) => {
                             if c.exists {
@@ -444,52 +2097,164 @@ pub async fn chat_task(
                                 notify.notify_waiters();
                             }
                         }
-                        ReceivedPacket::LoginOk
-                        | ReceivedPacket::BuddyRemove(_)
-                        | ReceivedPacket::BuddyStatus(_)
+                        ReceivedPacket::BuddyStatus(b) => {
+                            chat_state.update_buddy_status(b.character_id, b.online);
+                        }
+                        ReceivedPacket::Ping(_) => {
+                            if let Some(sent) = ping_sent.take() {
+                                latency = Some(sent.elapsed());
+                                let _ = ui_update_sender.send(UiUpdate::Latency(sent.elapsed()));
+                            }
+                        }
+                        ReceivedPacket::LoginOk => {
+                            if logged_in_before {
+                                let _ = ui_update_sender.send(UiUpdate::Reconnected);
+                            }
+                            logged_in_before = true;
+                            logged_in_at = Some(Instant::now());
+                            chat_state.logged_in.store(true, Ordering::Relaxed);
+                            let _ = ui_update_sender.send(UiUpdate::Connection(ConnectionState::Ready));
+                            let _ = ui_update_sender.send(UiUpdate::Status(format!("Logged in as {}", config.character_name)));
+                            if !read_only && !config.autojoin.is_empty() {
+                                let chat_state = chat_state.clone();
+                                let owners = config.autojoin.clone();
+                                tokio::spawn(async move { chat_state.autojoin(owners).await });
+                            }
+                            let chat_state = chat_state.clone();
+                            tokio::spawn(async move { chat_state.flush_outbox().await });
+                        }
+                        ReceivedPacket::BuddyRemove(_)
                         | ReceivedPacket::ChatNotice(_)
                         | ReceivedPacket::PrivgrpClijoin(_)
                         | ReceivedPacket::PrivgrpClipart(_)
-                        | ReceivedPacket::PrivgrpKick(_)
-                        | ReceivedPacket::MsgSystem(_)
-                        | ReceivedPacket::Ping(_) => {}
+                        | ReceivedPacket::MsgSystem(_) => {}
                     }
                 } else {
-                    break;
+                    if matches!(logged_in_at.take(), Some(at) if at.elapsed() < QUICK_DROP_TIME) {
+                        quick_drops += 1;
+                    } else {
+                        quick_drops = 0;
+                    }
+                    // This may be another client on the same character, which reconnecting
+                    // would keep logging out, so give up rather than fight over the session
+                    if quick_drops >= QUICK_DROP_LIMIT {
+                        let _ = ui_update_sender.send(UiUpdate::Fatal(String::from(
+                            "Disconnected repeatedly right after login; another client may be using this character",
+                        )));
+                        return Ok(());
+                    }
+                    let _ = ui_update_sender.send(UiUpdate::Connection(ConnectionState::Disconnected));
+                    let _ = ui_update_sender.send(UiUpdate::Status(String::from("Disconnected, reconnecting")));
+                    sleep(RECONNECT_DELAY).await;
+                    sock = reconnect(&connector, &chat_state, &config).await;
+                    ping_sent = None;
+                }
+            },
+            () = sleep_until(ping_sent.unwrap_or_else(Instant::now) + config.ping_timeout), if ping_sent.is_some() => {
+                // Some networks drop idle connections without the socket ever failing
+                let _ = ui_update_sender.send(UiUpdate::Connection(ConnectionState::Disconnected));
+                let _ = ui_update_sender.send(UiUpdate::Status(String::from("No answer to ping, reconnecting")));
+                sock = reconnect(&connector, &chat_state, &config).await;
+                ping_sent = None;
+            },
+            _ = ping_interval.tick() => {
+                // Time out relative to the oldest unanswered ping
+                if ping_sent.is_none() {
+                    ping_sent = Some(Instant::now());
                 }
+                let pack = PingPacket {
+                    client: String::from("ao-chat-client"),
+                };
+                sock.sender().send(pack).await?;
             },
             command = command_receiver.recv() => {
                 if let Some(cmd) = command {
                     match cmd {
-                        Command::Invite(user_name) => {
+                        Command::Invite(recipient) => {
                             let chat_state = chat_state.clone();
-                            tokio::spawn(async move { chat_state.invite(user_name).await });
+                            tokio::spawn(async move { chat_state.invite(recipient).await });
                         }
-                        Command::Kick(user_name) => {
+                        Command::InviteAll(user_names) => {
                             let chat_state = chat_state.clone();
-                            tokio::spawn(async move { chat_state.kick(user_name).await });
+                            tokio::spawn(async move { chat_state.invite_all(user_names).await });
+                        }
+                        Command::InviteFile(path) => {
+                            let chat_state = chat_state.clone();
+                            tokio::spawn(async move { chat_state.invite_file(path).await });
+                        }
+                        Command::Kick(recipient) => {
+                            let chat_state = chat_state.clone();
+                            tokio::spawn(async move { chat_state.kick(recipient).await });
                         }
                         Command::Leave(user_name) => {
                             let chat_state = chat_state.clone();
                             tokio::spawn(async move { chat_state.leave(user_name).await });
                         }
-                        Command::Tell(user_name, text) => {
+                        Command::Join(user_name) => {
+                            let chat_state = chat_state.clone();
+                            tokio::spawn(async move { chat_state.join(user_name).await });
+                        }
+                        Command::JoinId(id) => {
+                            let chat_state = chat_state.clone();
+                            tokio::spawn(async move { chat_state.join_id(id).await });
+                        }
+                        Command::Tell(user_names, text) => {
+                            let chat_state = chat_state.clone();
+                            tokio::spawn(async move { chat_state.send_tell(user_names, text).await });
+                        }
+                        Command::Org(text) => {
+                            let chat_state = chat_state.clone();
+                            tokio::spawn(async move { chat_state.send_org_message(text).await });
+                        }
+                        Command::SendFile(channel, path) => {
+                            let chat_state = chat_state.clone();
+                            tokio::spawn(async move { chat_state.send_file(&channel, path).await });
+                        }
+                        Command::Broadcast(text) => {
                             let chat_state = chat_state.clone();
-                            tokio::spawn(async move { chat_state.send_tell(user_name, text).await });
+                            tokio::spawn(async move { chat_state.broadcast(text).await });
                         }
+                        Command::Seen(user_name) => {
+                            let chat_state = chat_state.clone();
+                            tokio::spawn(async move { chat_state.seen(user_name).await });
+                        }
+                        Command::Reconnect => {
+                            let _ = ui_update_sender.send(UiUpdate::Status(String::from("Reconnecting")));
+                            sock = reconnect(&connector, &chat_state, &config).await;
+                            ping_sent = None;
+                        }
+                        Command::Debug => chat_state.debug(&config),
+                        Command::Stats => chat_state.stats(),
+                        Command::Server => chat_state.server(&config, latency),
+                        Command::WhoAmI => chat_state.whoami(&config),
+                        Command::Ignore(user_name, add) => chat_state.ignore(&user_name, add),
+                        Command::Highlight(word, add) => chat_state.highlight(&word, add),
+                        Command::Close(user_name) => chat_state.close(&user_name),
+                        Command::Mute(channel, add) => chat_state.mute(&channel, add),
+                        Command::Outbox(clear) => chat_state.outbox(clear),
+                        Command::ClearInvites => chat_state.clear_invites(),
+                        Command::ChannelAlias(channel, alias) => chat_state.alias_channel(&channel, alias),
                         Command::Message(channel, text) => {
                             let chat_state = chat_state.clone();
                             tokio::spawn(async move { chat_state.send_message(channel, text).await });
                         }
                     }
+                } else {
+                    // The UI is gone, nobody is left to talk to
+                    break;
                 }
             },
             query = state_query_receiver.recv() => {
                 if let Some(query) = query {
                     match query {
                         StateQuery::Channels(sender) => {
-                            let channels: Vec<ResolvedChannel> = chat_state.channels.read().unwrap().iter().map(|channel| ResolvedChannel::new(&chat_state, channel)).collect();
-                            let _ = sender.send(channels);
+                            let _ = sender.send(chat_state.resolved_channels());
+                        }
+                        StateQuery::Diagnostics(sender) => {
+                            let _ = sender.send(chat_state.diagnostics());
+                        }
+                        StateQuery::Invites(sender) => {
+                            let _ = sender.send(chat_state.resolved_invites());
                         }
                     }
                 }
@@ -499,3 +2264,576 @@ pub async fn chat_task(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env::temp_dir,
+        path::PathBuf,
+        process,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use chrono::TimeZone;
+    use nadylib::packets::{
+        ClientLookupResultPacket, ClientNamePacket, LoginCharlistPacket, LoginErrorPacket,
+        LoginSeedPacket, MsgPrivatePacket, MsgVicinityPacket, PrivgrpInvitePacket,
+        PrivgrpKickPacket, PrivgrpMessagePacket,
+    };
+    use tokio::sync::{mpsc::unbounded_channel, oneshot};
+
+    use super::*;
+    use crate::transport::mock;
+
+    /// Long enough for pings to time out, as tests with a paused clock skip ahead
+    const TEST_TIMEOUT: Duration = Duration::from_secs(200);
+
+    /// Numbers the harnesses so tests running in parallel don't share files
+    static NEXT_HARNESS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Runs the chat task against mock sockets.
+    struct Harness {
+        commands: UnboundedSender<Command>,
+        updates: UnboundedReceiver<UiUpdate>,
+        /// Feeds packets to the sockets, in the order they are connected
+        packets: Vec<UnboundedSender<ReceivedPacket>>,
+        sent: UnboundedReceiver<String>,
+        queries: UnboundedSender<StateQuery>,
+    }
+
+    /// A path no other test or test run uses, so state saved by one doesn't leak into another.
+    fn test_file(name: &str) -> PathBuf {
+        let harness = NEXT_HARNESS.fetch_add(1, Ordering::Relaxed);
+        temp_dir().join(format!(
+            "ao-chat-client-test-{}-{}-{}.json",
+            process::id(),
+            harness,
+            name
+        ))
+    }
+
+    impl Harness {
+        fn start(sockets: usize) -> Self {
+            Self::with_config(sockets, "")
+        }
+
+        /// Starts the chat task with `extra` appended to the minimal config.
+        fn with_config(sockets: usize, extra: &str) -> Self {
+            let config = config::parse(&format!(
+                "USERNAME=user\nPASSWORD=secret\nCHARNAME=Tester\n{}",
+                extra
+            ))
+            .unwrap()
+            .remove(0);
+            let (connector, packets, sent) = mock::connector(sockets);
+            let (queries, query_receiver) = unbounded_channel();
+            let (commands, command_receiver) = unbounded_channel();
+            let (update_sender, updates) = unbounded_channel();
+
+            tokio::spawn(chat_task(
+                connector,
+                query_receiver,
+                command_receiver,
+                update_sender,
+                config,
+                false,
+                WordList::load(test_file("ignore")),
+                WordList::load(test_file("highlights")),
+                ChannelAliases::load(test_file("channel-aliases")),
+            ));
+
+            Self {
+                commands,
+                updates,
+                packets,
+                sent,
+                queries,
+            }
+        }
+
+        fn receive(&self, packet: ReceivedPacket) {
+            self.packets[0].send(packet).unwrap();
+        }
+
+        /// Makes `name` known under `id`, like the server does before using an id.
+        fn name(&self, id: u32, name: &str) {
+            self.receive(ReceivedPacket::ClientName(ClientNamePacket {
+                character_id: id,
+                character_name: name.to_string(),
+            }));
+        }
+
+        /// Waits for the first UI update that `pick` returns something for.
+        async fn update<T>(&mut self, pick: impl Fn(UiUpdate) -> Option<T>) -> T {
+            loop {
+                let update = timeout(TEST_TIMEOUT, self.updates.recv())
+                    .await
+                    .expect("no matching UI update")
+                    .unwrap();
+                if let Some(picked) = pick(update) {
+                    return picked;
+                }
+            }
+        }
+
+        /// Waits for a sent packet whose `Debug` form starts with `prefix`.
+        async fn sent(&mut self, prefix: &str) -> String {
+            loop {
+                let packet = timeout(TEST_TIMEOUT, self.sent.recv())
+                    .await
+                    .expect("no matching packet sent")
+                    .unwrap();
+                if packet.starts_with(prefix) {
+                    return packet;
+                }
+            }
+        }
+
+        async fn connection(&mut self, state: ConnectionState) {
+            self.update(|update| match update {
+                UiUpdate::Connection(s) if s == state => Some(()),
+                _ => None,
+            })
+            .await;
+        }
+
+        /// Completes the login, so messages are sent instead of queued.
+        async fn log_in(&mut self) {
+            self.receive(ReceivedPacket::LoginOk);
+            self.connection(ConnectionState::Ready).await;
+        }
+    }
+
+    #[test]
+    fn dates_follow_the_timestamp_zone() {
+        let time = Utc
+            .with_ymd_and_hms(2024, 1, 1, 23, 30, 0)
+            .unwrap()
+            .with_timezone(&Local);
+        let utc = RenderOptions {
+            utc: true,
+            ..RenderOptions::default()
+        };
+
+        assert_eq!(utc.date(time), NaiveDate::from_ymd_opt(2024, 1, 1).unwrap());
+        assert_eq!(RenderOptions::default().date(time), time.date_naive());
+    }
+
+    #[tokio::test]
+    async fn login_seed_logs_in() {
+        let mut harness = Harness::start(1);
+        harness.receive(ReceivedPacket::LoginSeed(LoginSeedPacket {
+            login_seed: String::from("seed"),
+        }));
+
+        harness.connection(ConnectionState::Authenticating).await;
+        harness.sent("login user").await;
+
+        harness.receive(ReceivedPacket::LoginOk);
+        harness.connection(ConnectionState::Ready).await;
+    }
+
+    #[tokio::test]
+    async fn autojoin_joins_after_login() {
+        let mut harness = Harness::with_config(1, "AUTOJOIN=Owner\n");
+        harness.name(1, "Owner");
+        harness.receive(ReceivedPacket::LoginOk);
+
+        let packet = harness.sent("PrivgrpJoinPacket").await;
+        assert!(packet.contains("PrivateChannel(1)"), "{}", packet);
+        let report = harness
+            .update(|update| match update {
+                UiUpdate::System(text) => Some(text),
+                _ => None,
+            })
+            .await;
+        assert_eq!(report, "Auto-joining Owner");
+    }
+
+    #[tokio::test]
+    async fn empty_character_list_is_fatal() {
+        let mut harness = Harness::start(1);
+        harness.receive(ReceivedPacket::LoginCharlist(LoginCharlistPacket {
+            characters: Vec::new(),
+        }));
+
+        let reason = harness
+            .update(|update| match update {
+                UiUpdate::Fatal(reason) => Some(reason),
+                _ => None,
+            })
+            .await;
+        assert_eq!(reason, "Account has no characters");
+    }
+
+    #[tokio::test]
+    async fn login_error_is_fatal() {
+        let mut harness = Harness::start(1);
+        harness.receive(ReceivedPacket::LoginError(LoginErrorPacket {
+            message: String::from("Wrong password"),
+        }));
+
+        let reason = harness
+            .update(|update| match update {
+                UiUpdate::Fatal(reason) => Some(reason),
+                _ => None,
+            })
+            .await;
+        assert_eq!(reason, "Login failed: Wrong password");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn repeated_drops_after_login_are_fatal() {
+        let mut harness = Harness::start(QUICK_DROP_LIMIT as usize);
+        harness.log_in().await;
+        for _ in 1..QUICK_DROP_LIMIT {
+            drop(harness.packets.remove(0));
+            harness.connection(ConnectionState::Reconnecting).await;
+            harness.receive(ReceivedPacket::LoginOk);
+            harness.connection(ConnectionState::Ready).await;
+        }
+        drop(harness.packets.remove(0));
+
+        let reason = harness
+            .update(|update| match update {
+                UiUpdate::Fatal(reason) => Some(reason),
+                _ => None,
+            })
+            .await;
+        assert_eq!(
+            reason,
+            "Disconnected repeatedly right after login; another client may be using this character"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn invites_are_throttled() {
+        let mut harness = Harness::start(1);
+        for (id, name) in [(1, "First"), (2, "Second"), (3, "Third")] {
+            harness.name(id, name);
+        }
+        harness.log_in().await;
+        harness
+            .commands
+            .send(Command::InviteAll(vec![
+                String::from("First"),
+                String::from("Second"),
+                String::from("Third"),
+            ]))
+            .unwrap();
+
+        let mut sent_at = Vec::new();
+        for _ in 0..3 {
+            harness.sent("OutPrivgrpInvitePacket").await;
+            sent_at.push(Instant::now());
+        }
+        for pair in sent_at.windows(2) {
+            assert!(pair[1] - pair[0] >= SEND_INTERVAL, "{:?}", sent_at);
+        }
+    }
+
+    #[tokio::test]
+    async fn kick_removes_the_private_channel() {
+        let mut harness = Harness::start(1);
+        harness.name(1, "Owner");
+        harness.log_in().await;
+        harness.commands.send(Command::JoinId(1)).unwrap();
+        harness.sent("PrivgrpJoinPacket").await;
+
+        harness.receive(ReceivedPacket::PrivgrpKick(PrivgrpKickPacket {
+            channel: Channel::PrivateChannel(1),
+        }));
+        let channel = harness
+            .update(|update| match update {
+                UiUpdate::Kicked(channel) => Some(channel),
+                _ => None,
+            })
+            .await;
+        assert_eq!(channel.render(), "#Owner");
+        let channels = harness
+            .update(|update| match update {
+                UiUpdate::Channels(channels) => Some(channels),
+                _ => None,
+            })
+            .await;
+        assert!(channels
+            .iter()
+            .all(|c| c.r#type != ChannelType::PrivateChannel));
+    }
+
+    #[tokio::test]
+    async fn joining_an_invite_removes_it() {
+        let mut harness = Harness::start(1);
+        for (id, name) in [(1, "Owner"), (2, "Other")] {
+            harness.name(id, name);
+            harness.receive(ReceivedPacket::PrivgrpInvite(PrivgrpInvitePacket {
+                channel: Channel::PrivateChannel(id),
+            }));
+        }
+        harness.log_in().await;
+        harness.commands.send(Command::JoinId(1)).unwrap();
+        harness
+            .update(|update| match update {
+                UiUpdate::Status(text) if text.starts_with("Joining") => Some(()),
+                _ => None,
+            })
+            .await;
+
+        let (tx, rx) = oneshot::channel();
+        harness.queries.send(StateQuery::Invites(tx)).unwrap();
+        let invites = timeout(TEST_TIMEOUT, rx).await.unwrap().unwrap();
+        let names: Vec<String> = invites.iter().map(ResolvedChannel::render).collect();
+        assert_eq!(names, ["#Other"]);
+    }
+
+    #[tokio::test]
+    async fn private_channel_message_resolves_names() {
+        let mut harness = Harness::start(1);
+        harness.name(1, "Owner");
+        harness.name(2, "Speaker");
+        harness.receive(ReceivedPacket::PrivgrpMessage(PrivgrpMessagePacket {
+            message: Message {
+                sender: Some(2),
+                channel: Channel::PrivateChannel(1),
+                text: String::from("hello"),
+                send_tag: String::new(),
+            },
+        }));
+
+        let msg = harness
+            .update(|update| match update {
+                UiUpdate::Message(msg) => Some(msg),
+                _ => None,
+            })
+            .await;
+        assert_eq!(msg.sender.as_deref(), Some("Speaker"));
+        assert_eq!(msg.channel.name, "Owner");
+        assert!(msg.channel.r#type == ChannelType::PrivateChannel);
+        assert_eq!(msg.text, "hello");
+        assert!(!msg.outgoing);
+    }
+
+    #[tokio::test]
+    async fn item_markup_becomes_a_link() {
+        let mut harness = Harness::start(1);
+        harness.name(1, "Owner");
+        harness.name(2, "Speaker");
+        harness.receive(ReceivedPacket::PrivgrpMessage(PrivgrpMessagePacket {
+            message: Message {
+                sender: Some(2),
+                channel: Channel::PrivateChannel(1),
+                text: String::from(
+                    "<font color=#FFFFFF>Selling</font>\u{1b}[2J <a href=\"itemref://1/2/300\">Item &amp; Co</a>",
+                ),
+                send_tag: String::new(),
+            },
+        }));
+
+        let msg = harness
+            .update(|update| match update {
+                UiUpdate::Message(msg) => Some(msg),
+                _ => None,
+            })
+            .await;
+        assert_eq!(msg.text, "Selling\u{fffd}[2J Item & Co");
+        assert_eq!(msg.links.len(), 1);
+        assert_eq!(&msg.text[msg.links[0].range.clone()], "Item & Co");
+        assert_eq!(msg.links[0].target, "itemref://1/2/300");
+    }
+
+    #[tokio::test]
+    async fn tell_looks_up_the_recipient() {
+        let mut harness = Harness::start(1);
+        harness.log_in().await;
+        // Outgoing messages are echoed with our own name
+        harness.name(0, "Tester");
+        harness
+            .commands
+            .send(Command::Tell(
+                vec![String::from("Friend")],
+                String::from("hi"),
+            ))
+            .unwrap();
+
+        harness.sent("ClientLookupPacket").await;
+        harness.receive(ReceivedPacket::ClientLookup(ClientLookupResultPacket {
+            character_id: 7,
+            exists: true,
+            character_name: String::from("Friend"),
+        }));
+
+        let packet = harness.sent("MsgPrivatePacket").await;
+        assert!(packet.contains("Tell(7)"), "{}", packet);
+        let channel = harness
+            .update(|update| match update {
+                UiUpdate::TellSent(channel) => Some(channel),
+                _ => None,
+            })
+            .await;
+        assert_eq!(channel.name, "Friend");
+    }
+
+    #[tokio::test]
+    async fn unknown_recipient_is_reported() {
+        let mut harness = Harness::start(1);
+        harness.log_in().await;
+        harness
+            .commands
+            .send(Command::Tell(
+                vec![String::from("Nobody")],
+                String::from("hi"),
+            ))
+            .unwrap();
+
+        harness.sent("ClientLookupPacket").await;
+        harness.receive(ReceivedPacket::ClientLookup(ClientLookupResultPacket {
+            character_id: u32::MAX,
+            exists: false,
+            character_name: String::from("Nobody"),
+        }));
+
+        harness
+            .update(|update| match update {
+                UiUpdate::Status(text) if text == "Could not resolve Nobody" => Some(()),
+                _ => None,
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn message_is_queued_until_login() {
+        let mut harness = Harness::start(1);
+        let channel = ResolvedChannel {
+            id: 1,
+            name: String::from("Owner"),
+            r#type: ChannelType::PrivateChannel,
+            alias: None,
+            prefixes: Arc::default(),
+        };
+        harness
+            .commands
+            .send(Command::Message(channel, String::from("hi")))
+            .unwrap();
+        harness
+            .update(|update| match update {
+                UiUpdate::System(text) if text.contains("queued [#Owner] hi") => Some(()),
+                _ => None,
+            })
+            .await;
+
+        harness.log_in().await;
+        let packet = harness.sent("PrivgrpMessagePacket").await;
+        assert!(packet.contains("\"hi\""), "{}", packet);
+    }
+
+    #[tokio::test]
+    async fn queued_group_message_without_the_group_is_reported() {
+        let mut harness = Harness::start(1);
+        let channel = ResolvedChannel {
+            id: 1,
+            name: String::from("Org"),
+            r#type: ChannelType::Org,
+            alias: None,
+            prefixes: Arc::default(),
+        };
+        harness
+            .commands
+            .send(Command::Message(channel, String::from("hi")))
+            .unwrap();
+
+        // The outbox is flushed before the server announces the group again
+        harness.log_in().await;
+        let status = harness
+            .update(|update| match update {
+                UiUpdate::Status(text) if text.starts_with("Could not send") => Some(text),
+                _ => None,
+            })
+            .await;
+        assert_eq!(status, "Could not send: channel Org is gone");
+    }
+
+    #[tokio::test]
+    async fn org_message_without_an_org_is_reported() {
+        let mut harness = Harness::start(1);
+        harness.log_in().await;
+        harness
+            .commands
+            .send(Command::Org(String::from("hi")))
+            .unwrap();
+
+        let status = harness
+            .update(|update| match update {
+                UiUpdate::Status(text) if text.contains("org") => Some(text),
+                _ => None,
+            })
+            .await;
+        assert_eq!(status, "You are not in an org");
+    }
+
+    #[tokio::test]
+    async fn muted_channel_is_dropped() {
+        let mut harness = Harness::start(1);
+        harness.name(2, "Speaker");
+        harness
+            .commands
+            .send(Command::Mute(String::from("vicinity"), true))
+            .unwrap();
+        harness
+            .update(|update| match update {
+                UiUpdate::Muted(channels) => Some(channels),
+                _ => None,
+            })
+            .await;
+
+        let message = |channel, text: &str| Message {
+            sender: Some(2),
+            channel,
+            text: text.to_string(),
+            send_tag: String::new(),
+        };
+        harness.receive(ReceivedPacket::MsgVicinity(MsgVicinityPacket {
+            message: message(Channel::Vicinity, "muted"),
+        }));
+        harness.receive(ReceivedPacket::MsgPrivate(MsgPrivatePacket {
+            message: message(Channel::Tell(2), "not muted"),
+        }));
+
+        let msg = harness
+            .update(|update| match update {
+                UiUpdate::Message(msg) => Some(msg),
+                _ => None,
+            })
+            .await;
+        assert_eq!(msg.text, "not muted");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lost_connection_reconnects() {
+        let mut harness = Harness::start(2);
+        harness.connection(ConnectionState::Connecting).await;
+        harness.log_in().await;
+
+        // Dropping the feed closes the first socket
+        drop(harness.packets.remove(0));
+        harness.connection(ConnectionState::Disconnected).await;
+        harness.connection(ConnectionState::Reconnecting).await;
+
+        harness.receive(ReceivedPacket::LoginOk);
+        harness
+            .update(|update| match update {
+                UiUpdate::Reconnected => Some(()),
+                _ => None,
+            })
+            .await;
+        harness.connection(ConnectionState::Ready).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unanswered_ping_reconnects() {
+        let mut harness = Harness::start(2);
+        harness.sent("PingPacket").await;
+
+        harness.connection(ConnectionState::Disconnected).await;
+        harness.connection(ConnectionState::Reconnecting).await;
+    }
+}
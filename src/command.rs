@@ -1,26 +1,547 @@
+use std::{collections::HashMap, iter::once, str::SplitAsciiWhitespace};
+
+/// A built-in command, as listed by `/help` and the command palette.
+pub struct CommandInfo {
+    pub name: &'static str,
+    /// Arguments, shown after the name
+    pub args: &'static str,
+    pub description: &'static str,
+}
+
+impl CommandInfo {
+    pub fn usage(&self) -> String {
+        format!("/{} {}", self.name, self.args)
+            .trim_end()
+            .to_string()
+    }
+}
+
+/// The built-in commands, which aliases cannot replace.
+pub const COMMANDS: &[CommandInfo] = &[
+    CommandInfo {
+        name: "tell",
+        args: "<name[,name...]> <message>",
+        description: "Send a tell to one or more characters",
+    },
+    CommandInfo {
+        name: "o",
+        args: "<message>",
+        description: "Send a message to your org",
+    },
+    CommandInfo {
+        name: "broadcast",
+        args: "<message>",
+        description: "Send a message to every private channel you are in",
+    },
+    CommandInfo {
+        name: "sendfile",
+        args: "<channel> <path>",
+        description: "Send a text file to a channel, one message per line",
+    },
+    CommandInfo {
+        name: "msgid",
+        args: "<owner id> <message>",
+        description: "Send a message to a private channel by its owner's id",
+    },
+    CommandInfo {
+        name: "invite",
+        args: "[name | name,name,... | @file <path>]",
+        description: "Invite to your private channel, the last sender without a name",
+    },
+    CommandInfo {
+        name: "kick",
+        args: "[name]",
+        description: "Kick from your private channel, the last sender without a name",
+    },
+    CommandInfo {
+        name: "join",
+        args: "<name>",
+        description: "Join a character's private channel",
+    },
+    CommandInfo {
+        name: "joinid",
+        args: "<owner id>",
+        description: "Join a private channel by its owner's id",
+    },
+    CommandInfo {
+        name: "leave",
+        args: "<name>",
+        description: "Leave a character's private channel",
+    },
+    CommandInfo {
+        name: "topic",
+        args: "[text]",
+        description: "Show or set a local note as the topic of the current private channel",
+    },
+    CommandInfo {
+        name: "quote",
+        args: "<id>",
+        description: "Quote a message in your next chat message, ids are shown by /toggle ids",
+    },
+    CommandInfo {
+        name: "close",
+        args: "<name>",
+        description: "Remove a tell conversation from the channel list",
+    },
+    CommandInfo {
+        name: "mute",
+        args: "<channel>",
+        description: "Drop incoming messages of a channel without leaving it",
+    },
+    CommandInfo {
+        name: "unmute",
+        args: "<channel>",
+        description: "Show messages of a muted channel again",
+    },
+    CommandInfo {
+        name: "alias",
+        args: "<channel> [nickname]",
+        description: "Show a channel under another name, or its real name again",
+    },
+    CommandInfo {
+        name: "seen",
+        args: "<name>",
+        description: "Show when a buddy was last online",
+    },
+    CommandInfo {
+        name: "ignore",
+        args: "<name>",
+        description: "Hide messages from a character",
+    },
+    CommandInfo {
+        name: "unignore",
+        args: "<name>",
+        description: "Show messages from an ignored character again",
+    },
+    CommandInfo {
+        name: "highlight",
+        args: "<word>",
+        description: "Highlight a word in messages",
+    },
+    CommandInfo {
+        name: "unhighlight",
+        args: "<word>",
+        description: "Stop highlighting a word",
+    },
+    CommandInfo {
+        name: "toggle",
+        args: "timestamps | prefixes | ids | follow",
+        description: "Toggle a display option",
+    },
+    CommandInfo {
+        name: "export",
+        args: "<path>",
+        description: "Write the scrollback to a file",
+    },
+    CommandInfo {
+        name: "outbox",
+        args: "[clear]",
+        description: "List or cancel the messages queued while disconnected",
+    },
+    CommandInfo {
+        name: "invites",
+        args: "[number|clear]",
+        description: "List the private channels you were invited to, join one or forget them",
+    },
+    CommandInfo {
+        name: "channels",
+        args: "",
+        description: "List the known channels",
+    },
+    CommandInfo {
+        name: "stats",
+        args: "",
+        description: "Show message counts and uptime",
+    },
+    CommandInfo {
+        name: "uptime",
+        args: "",
+        description: "Same as /stats",
+    },
+    CommandInfo {
+        name: "whoami",
+        args: "",
+        description: "Show the character and account you are logged in with",
+    },
+    CommandInfo {
+        name: "server",
+        args: "",
+        description: "Show the dimension and server you are connected to",
+    },
+    CommandInfo {
+        name: "dimension",
+        args: "",
+        description: "Same as /server",
+    },
+    CommandInfo {
+        name: "reconnect",
+        args: "",
+        description: "Reconnect to the chat server",
+    },
+    CommandInfo {
+        name: "debug",
+        args: "",
+        description: "Show the configuration and connection state",
+    },
+    CommandInfo {
+        name: "help",
+        args: "",
+        description: "List the commands",
+    },
+];
+
+pub fn is_builtin(name: &str) -> bool {
+    COMMANDS.iter().any(|command| command.name == name)
+}
+
+/// Lists the commands with their arguments, one per line.
+pub fn help() -> String {
+    once(String::from("Commands:"))
+        .chain(
+            COMMANDS
+                .iter()
+                .map(|command| format!("{} - {}", command.usage(), command.description)),
+        )
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub enum Toggle {
+    Timestamps,
+    Prefixes,
+    /// Message ids, as used by `/quote`
+    Ids,
+    /// Following the channel of the latest incoming message
+    Follow,
+}
+
 pub enum Command {
-    Invite(String),
-    Kick(String),
+    Invite(Option<String>),
+    /// Invites several comma-separated characters, one after another
+    InviteAll(Vec<String>),
+    /// Invites the characters listed in a file
+    InviteFile(String),
+    Kick(Option<String>),
     Leave(String),
-    Tell(String, String),
+    /// Joins the private channel of a character by name
+    Join(String),
+    /// Tell to one or more comma-separated recipients
+    Tell(Vec<String>, String),
+    Org(String),
+    /// Sends a message to every joined private channel
+    Broadcast(String),
+    /// Sends the lines of a file to a channel
+    SendFile(String, String),
+    Seen(String),
+    Reconnect,
+    Channels,
+    Debug,
+    Ignore(String),
+    Unignore(String),
+    Highlight(String),
+    Unhighlight(String),
+    Export(String),
+    Stats,
+    /// Shows the dimension, server address and latency
+    Server,
+    /// Shows the logged-in character and account
+    WhoAmI,
+    /// Private channel message by owner id, without a name lookup
+    MessageId(u32, String),
+    JoinId(u32),
+    Toggle(Toggle),
+    /// Shows or, with text, sets the topic of the current private channel
+    Topic(Option<String>),
+    /// Quotes the message with the given id in the chat draft
+    Quote(u64),
+    /// Removes a tell conversation from the channel list
+    Close(String),
+    /// Drops incoming messages of a channel without leaving it
+    Mute(String),
+    Unmute(String),
+    /// Sets the local display name of a channel, or removes it without a name
+    ChannelAlias(String, Option<String>),
+    /// Lists the messages queued while disconnected, or cancels them if true
+    Outbox(bool),
+    /// Lists the private channels invited to during this session
+    Invites,
+    /// Joins the private channel with the given number in the invite list, from 1
+    JoinInvite(usize),
+    /// Forgets every invite
+    ClearInvites,
+    Help,
+}
+
+/// Whether `name` follows the character name rules: 4 to 12 letters, digits or dashes,
+/// starting with a letter. Names that don't can't exist, so there is no point asking
+/// the server about them.
+pub fn is_valid_character_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    (4..=12).contains(&name.len())
+        && matches!(chars.next(), Some(c) if c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// Splits a comma-separated list of names, skipping empty ones.
+fn split_names(names: &str) -> Vec<String> {
+    names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Collects a comma-separated list of names starting with `first`, also when there
+/// are spaces after the commas. The words after the last name stay in `params`.
+fn name_list(first: &str, params: &mut SplitAsciiWhitespace) -> String {
+    let mut names = first.to_string();
+    while names.ends_with(',') {
+        if let Some(next) = params.next() {
+            names.push_str(next);
+        } else {
+            break;
+        }
+    }
+    names
+}
+
+fn join_rest(params: SplitAsciiWhitespace) -> String {
+    let rest = params.fold(String::new(), |a, b| a + b + " ");
+    rest.trim().to_string()
+}
+
+/// The text after the first `words` words of `command` exactly as typed, for paths
+/// that may contain runs of spaces. Only the space right after the last word is dropped.
+fn raw_rest(command: &str, words: usize) -> &str {
+    let mut rest = command;
+    for _ in 0..words {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace());
+        rest = rest.trim_start_matches(|c: char| !c.is_ascii_whitespace());
+    }
+    rest.get(1..).unwrap_or_default()
+}
+
+/// Expands a user-defined alias at the start of `input`. `$1` to `$9` in the alias are
+/// replaced by the matching argument and `$*` by all of them, otherwise the arguments
+/// are appended. Returns `None` if `input` does not start with an alias.
+pub fn expand_alias(input: &str, aliases: &HashMap<String, String>) -> Option<String> {
+    let command = input.strip_prefix('/').unwrap_or(input);
+    let mut params = command.split_ascii_whitespace();
+    let expansion = aliases.get(params.next()?)?;
+    let args: Vec<&str> = params.collect();
+
+    if !expansion.contains('$') {
+        return Some(
+            format!("{} {}", expansion, args.join(" "))
+                .trim_end()
+                .to_string(),
+        );
+    }
+
+    let mut expanded = expansion.replace("$*", &args.join(" "));
+    for i in 1..=9 {
+        let arg = args.get(i - 1).copied().unwrap_or_default();
+        expanded = expanded.replace(&format!("${}", i), arg);
+    }
+
+    Some(expanded)
 }
 
 impl Command {
+    /// Whether the command only reads state and can run in read-only mode.
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Self::Seen(_)
+                | Self::Reconnect
+                | Self::Channels
+                | Self::Debug
+                | Self::Ignore(_)
+                | Self::Unignore(_)
+                | Self::Highlight(_)
+                | Self::Unhighlight(_)
+                | Self::Export(_)
+                | Self::Stats
+                | Self::Server
+                | Self::WhoAmI
+                | Self::Toggle(_)
+                | Self::Topic(_)
+                | Self::Close(_)
+                | Self::Mute(_)
+                | Self::Unmute(_)
+                | Self::Outbox(_)
+                | Self::Invites
+                | Self::ClearInvites
+                | Self::ChannelAlias(..)
+                | Self::Quote(_)
+                | Self::Help
+        )
+    }
+
+    /// Whether the command is held back until the login completes, instead of
+    /// refused while not connected.
+    pub fn is_queued(&self) -> bool {
+        matches!(self, Self::Tell(..))
+    }
+
+    /// Whether the command targets the last sender in the current channel.
+    pub fn has_implicit_target(&self) -> bool {
+        matches!(self, Self::Invite(None) | Self::Kick(None))
+    }
+
     pub fn from_input(input: &str) -> Option<Self> {
         let command = input.strip_prefix('/').unwrap_or(input);
         let mut params = command.split_ascii_whitespace();
         let name = params.next()?;
-        let user = params.next()?;
 
-        let mut rest = params.fold(String::new(), |a, b| a + b + " ");
-        rest = rest.trim().to_string();
+        match name {
+            "reconnect" => return Some(Self::Reconnect),
+            "channels" => return Some(Self::Channels),
+            "debug" => return Some(Self::Debug),
+            "stats" | "uptime" => return Some(Self::Stats),
+            "server" | "dimension" => return Some(Self::Server),
+            "whoami" => return Some(Self::WhoAmI),
+            "help" => return Some(Self::Help),
+            _ => {}
+        }
+
+        if name == "topic" {
+            let text = join_rest(params);
+            return Some(Self::Topic(if text.is_empty() { None } else { Some(text) }));
+        }
+
+        if name == "outbox" {
+            return match params.next() {
+                None => Some(Self::Outbox(false)),
+                Some("clear") => Some(Self::Outbox(true)),
+                Some(_) => None,
+            };
+        }
+
+        if name == "invites" {
+            return match params.next() {
+                None => Some(Self::Invites),
+                Some("clear") => Some(Self::ClearInvites),
+                Some(number) => Some(Self::JoinInvite(number.parse().ok()?)),
+            };
+        }
+
+        if name == "o" || name == "broadcast" {
+            let text = join_rest(params);
+            if text.is_empty() {
+                return None;
+            }
+            return Some(if name == "o" {
+                Self::Org(text)
+            } else {
+                Self::Broadcast(text)
+            });
+        }
+
+        let maybe_user = params.next();
+
+        if let ("invite", Some("@file")) = (name, maybe_user) {
+            let path = raw_rest(command, 2);
+            return if path.trim().is_empty() {
+                None
+            } else {
+                Some(Self::InviteFile(path.to_string()))
+            };
+        }
+        if name == "export" {
+            let path = raw_rest(command, 1);
+            return if path.trim().is_empty() {
+                None
+            } else {
+                Some(Self::Export(path.to_string()))
+            };
+        }
+        if let ("invite", Some(first)) = (name, maybe_user) {
+            let users = once(first).chain(&mut params).collect::<Vec<_>>().join(" ");
+            if users.contains(',') {
+                return Some(Self::InviteAll(split_names(&users)));
+            }
+        }
+        if let ("tell", Some(first)) = (name, maybe_user) {
+            let users = split_names(&name_list(first, &mut params));
+            if users.is_empty() {
+                return None;
+            }
+            return Some(Self::Tell(users, join_rest(params)));
+        }
+
+        // Without a name these target the last sender in the current channel
+        match name {
+            "invite" => return Some(Self::Invite(maybe_user.map(str::to_string))),
+            "kick" => return Some(Self::Kick(maybe_user.map(str::to_string))),
+            _ => {}
+        }
+
+        let user = maybe_user?;
+        let rest = join_rest(params);
 
         match name {
-            "invite" => Some(Self::Invite(user.to_string())),
-            "kick" => Some(Self::Kick(user.to_string())),
             "leave" => Some(Self::Leave(user.to_string())),
-            "tell" => Some(Self::Tell(user.to_string(), rest)),
+            "join" => Some(Self::Join(user.to_string())),
+            "seen" => Some(Self::Seen(user.to_string())),
+            "ignore" => Some(Self::Ignore(user.to_string())),
+            "unignore" => Some(Self::Unignore(user.to_string())),
+            "highlight" => Some(Self::Highlight(user.to_string())),
+            "unhighlight" => Some(Self::Unhighlight(user.to_string())),
+            "close" => Some(Self::Close(user.to_string())),
+            "mute" => Some(Self::Mute(user.to_string())),
+            "unmute" => Some(Self::Unmute(user.to_string())),
+            "alias" => Some(Self::ChannelAlias(
+                user.to_string(),
+                if rest.is_empty() { None } else { Some(rest) },
+            )),
+            "sendfile" if !rest.is_empty() => Some(Self::SendFile(
+                user.to_string(),
+                raw_rest(command, 2).to_string(),
+            )),
+            "msgid" if !rest.is_empty() => Some(Self::MessageId(user.parse().ok()?, rest)),
+            "joinid" => Some(Self::JoinId(user.parse().ok()?)),
+            "quote" => Some(Self::Quote(user.parse().ok()?)),
+            "toggle" => match user {
+                "timestamps" => Some(Self::Toggle(Toggle::Timestamps)),
+                "prefixes" => Some(Self::Toggle(Toggle::Prefixes)),
+                "ids" => Some(Self::Toggle(Toggle::Ids)),
+                "follow" => Some(Self::Toggle(Toggle::Follow)),
+                _ => None,
+            },
             _ => None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_keep_their_spaces() {
+        assert!(matches!(
+            Command::from_input("/export My  Logs/x.txt "),
+            Some(Command::Export(path)) if path == "My  Logs/x.txt "
+        ));
+        assert!(matches!(
+            Command::from_input("/export  leading.txt"),
+            Some(Command::Export(path)) if path == " leading.txt"
+        ));
+        assert!(matches!(
+            Command::from_input("/sendfile Org My  Notes.txt"),
+            Some(Command::SendFile(channel, path)) if channel == "Org" && path == "My  Notes.txt"
+        ));
+        assert!(matches!(
+            Command::from_input("/invite @file  names list.txt"),
+            Some(Command::InviteFile(path)) if path == " names list.txt"
+        ));
+    }
+
+    #[test]
+    fn paths_must_not_be_blank() {
+        assert!(Command::from_input("/export   ").is_none());
+        assert!(Command::from_input("/invite @file ").is_none());
+    }
+}
@@ -1,36 +1,619 @@
-use std::{fs::read_to_string, path::Path};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    fs::read_to_string,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
+use crate::{chat::ChannelType, theme::Theme};
+
+const DEFAULT_SCROLLBACK: usize = 5000;
+const DEFAULT_PROFILE: &str = "default";
+const DEFAULT_SEND_TAG: &str = "\u{0}";
+const DEFAULT_SERVER: &str = "chat.d1.funcom.com:7105";
+const DEFAULT_POPUP_WIDTH: u16 = 60;
+const DEFAULT_POPUP_HEIGHT: u16 = 50;
+const DEFAULT_FLOOD_THRESHOLD: usize = 20;
+const DEFAULT_PING_INTERVAL: u64 = 30;
+const DEFAULT_PING_TIMEOUT: u64 = 60;
+/// Chat servers of the official dimensions
+const DIMENSIONS: &[(&str, &str)] = &[
+    ("chat.d1.funcom.com:7105", "Rubi-Ka"),
+    ("chat.d1.funcom.com:7106", "Rubi-Ka 2019"),
+    ("chat.dt.funcom.com:7109", "Test"),
+];
+
+/// Written when no config file exists. Lines that do not start with a key are ignored,
+/// so optional keys are listed commented out. Keep this in sync with `profile`.
+pub const TEMPLATE: &str = "\
+# Account and character to log in with
+USERNAME=
+PASSWORD=
+CHARNAME=
+
+# Multiple characters can be configured as [name] sections, keys above them
+# act as defaults for every section. Pick one with --profile <name>.
+# [alt]
+# CHARNAME=
+
+# Number of lines kept in the chat pane
+# SCROLLBACK=5000
+
+# Comma-separated words to highlight, case-insensitive
+# HIGHLIGHTS=
+# Regex to highlight, one per line, may be repeated
+# HIGHLIGHT_REGEX=
+
+# Warn in the status bar above this many incoming messages per second, 0 to disable
+# FLOOD_THRESHOLD=20
+# Show identical consecutive messages once with a (x3) count: true or false
+# COALESCE_REPEATS=false
+
+# Ring the terminal bell on: tell, highlight (comma-separated)
+# BELL_ON=
+
+# Time zone of message timestamps: local or utc
+# TIMESTAMP_TZ=local
+
+# Comma-separated characters whose private channels are joined after logging in
+# AUTOJOIN=
+
+# File with one keyword=response rule per line. Tells containing a keyword are
+# answered with the response, at most once per character every five minutes.
+# AUTO_RESPONSES=
+
+# Switch the chat channel to the recipient after a /tell: true or false
+# FOCUS_TELLS=false
+# Switch the chat channel to where the latest message came from, unless you are
+# typing: true or false. Toggle with /toggle follow.
+# FOCUS_FOLLOW=false
+# Go back to chat mode after running a command: true or false
+# RETURN_TO_CHAT_AFTER_COMMAND=false
+
+# Show only a colored one-character mode indicator in the status bar: true or false.
+# MINIMAL_STATUSBAR=false
+# Draw a border around the chat pane, titled with the current channel: true or false
+# CHAT_BORDER=false
+
+# Size of popups like the channel switcher, in percent of the terminal
+# POPUP_WIDTH=60
+# POPUP_HEIGHT=50
+
+# Log out and exit after this many minutes without keyboard input, for shared
+# machines. Empty or 0 to stay logged in.
+# IDLE_LOGOUT_MINUTES=
+
+# Set to false to draw inline instead of on the alternate screen
+# ALT_SCREEN=true
+
+# Custom commands, /w Name hi expands to /tell Name hi. Use $1 to $9 for single
+# arguments and $* for all of them, otherwise arguments are appended.
+# ALIAS_w=/tell
+
+# Seconds between pings to the server, and how long to wait for an answer before
+# reconnecting. This catches connections that were dropped without an error.
+# PING_INTERVAL=30
+# PING_TIMEOUT=60
+
+# Chat server to connect to, for example a local emulator
+# SERVER=chat.d1.funcom.com:7105
+# How to log in: seed waits for the login seed like the live servers, direct
+# logs in right after connecting for test servers that skip it. Needs SERVER.
+# LOGIN=seed
+
+# Send tag attached to outgoing messages
+# SEND_TAG=
+
+# Text put before and after every chat message and tell you send, separated
+# from it by a space. Commands are left alone.
+# MESSAGE_PREFIX=
+# MESSAGE_SUFFIX=
+
+# Symbols put before channel names, for example an emoji per channel type.
+# Anonymous vicinity messages show the vicinity symbol twice.
+# PREFIX_GROUP=*
+# PREFIX_ORG=~
+# PREFIX_PRIVATE=#
+# PREFIX_TELL=@
+# PREFIX_VICINITY=.
+
+# Unix socket that accepts /-commands, one per line
+# CONTROL_SOCKET=
+
+# Colors as a name, r,g,b or #rrggbb
+# COLOR_BACKGROUND=
+# COLOR_FOREGROUND=
+# COLOR_COMMAND_BAR=
+# COLOR_COMMAND_BAR_TEXT=
+# COLOR_SCROLL_BAR=
+# COLOR_SCROLL_BAR_TEXT=
+# COLOR_CHAT_BAR=
+# COLOR_CHAT_BAR_TEXT=
+# COLOR_INPUT_BAR=
+# COLOR_INPUT_BAR_TEXT=
+# COLOR_TELL=
+# COLOR_OUTGOING=
+# COLOR_SYSTEM=
+# COLOR_HIGHLIGHT=
+# COLOR_WARNING=
+# COLOR_LINK=
+";
+
+/// Why the config file could not be loaded.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    MissingKey(String),
+    EmptyValue(String),
+    InvalidValue(String),
+    Profile(String, Box<ConfigError>),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Could not read the config file: {}", e),
+            Self::MissingKey(key) => write!(f, "{} is missing from the config file", key),
+            Self::EmptyValue(key) => write!(f, "{} is empty, please fill it in", key),
+            Self::InvalidValue(key) => write!(f, "{} has an invalid value", key),
+            Self::Profile(name, e) => write!(f, "Profile {}: {}", name, e),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Events that ring the terminal bell.
+#[derive(Clone, Copy, Default)]
+pub struct BellTriggers {
+    pub tell: bool,
+    pub highlight: bool,
+}
+
+/// Text put around outgoing chat messages, like a raid tag or a signature.
+#[derive(Clone, Default)]
+pub struct Decoration {
+    pub prefix: String,
+    pub suffix: String,
+}
+
+impl Decoration {
+    /// Adds the prefix and suffix to `text`, separated from it by spaces.
+    pub fn apply(&self, text: &str) -> String {
+        let mut decorated =
+            String::with_capacity(self.prefix.len() + text.len() + self.suffix.len() + 2);
+        if !self.prefix.is_empty() {
+            decorated.push_str(&self.prefix);
+            decorated.push(' ');
+        }
+        decorated.push_str(text);
+        if !self.suffix.is_empty() {
+            decorated.push(' ');
+            decorated.push_str(&self.suffix);
+        }
+        decorated
+    }
+
+    /// Takes the prefix and suffix off `text` again, where it has them.
+    pub fn strip<'a>(&self, text: &'a str) -> &'a str {
+        let text = if self.prefix.is_empty() {
+            text
+        } else {
+            text.strip_prefix(self.prefix.as_str())
+                .and_then(|t| t.strip_prefix(' '))
+                .unwrap_or(text)
+        };
+        if self.suffix.is_empty() {
+            text
+        } else {
+            text.strip_suffix(self.suffix.as_str())
+                .and_then(|t| t.strip_suffix(' '))
+                .unwrap_or(text)
+        }
+    }
+}
+
+/// Symbols that channel names are shown with, one per channel type.
+pub struct ChannelPrefixes {
+    pub group: String,
+    pub org: String,
+    pub private_channel: String,
+    pub tell: String,
+    pub vicinity: String,
+}
+
+impl Default for ChannelPrefixes {
+    fn default() -> Self {
+        Self {
+            group: String::from("*"),
+            org: String::from("~"),
+            private_channel: String::from("#"),
+            tell: String::from("@"),
+            vicinity: String::from("."),
+        }
+    }
+}
+
+impl ChannelPrefixes {
+    pub fn get(&self, r#type: ChannelType) -> &str {
+        match r#type {
+            ChannelType::Group => &self.group,
+            ChannelType::Org => &self.org,
+            ChannelType::PrivateChannel => &self.private_channel,
+            ChannelType::Tell => &self.tell,
+            ChannelType::Vicinity | ChannelType::AnonymousVicinity => &self.vicinity,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Config {
+    pub profile: String,
     pub character_name: String,
     pub user_name: String,
     pub password: String,
+    pub scrollback: usize,
+    pub theme: Theme,
+    pub highlights: Vec<String>,
+    pub highlight_patterns: Vec<String>,
+    /// Owners of the private channels to join after logging in
+    pub autojoin: Vec<String>,
+    pub bell_on: BellTriggers,
+    /// Unix socket to accept scripted commands on
+    pub control_socket: Option<PathBuf>,
+    /// File that every received packet is logged to, for debugging
+    pub packet_log: Option<PathBuf>,
+    /// File with `keyword=response` rules to answer tells with
+    pub auto_responses: Option<PathBuf>,
+    /// Draw on the alternate screen instead of inline
+    pub alt_screen: bool,
+    /// Send tag attached to outgoing messages
+    pub send_tag: String,
+    pub decoration: Decoration,
+    pub prefixes: Arc<ChannelPrefixes>,
+    /// Address of the chat server
+    pub server: String,
+    /// Log in without waiting for a login seed
+    pub direct_login: bool,
+    pub ping_interval: Duration,
+    /// Time without an answer to a ping after which the connection counts as lost
+    pub ping_timeout: Duration,
+    /// Switch the chat channel to the recipient of a `/tell`
+    pub focus_tells: bool,
+    /// Switch the chat channel to where the latest incoming message came from
+    pub focus_follow: bool,
+    /// Switch back to chat mode after a command was submitted
+    pub return_to_chat: bool,
+    /// Replace the mode name in the status bar by a one-character indicator
+    pub minimal_statusbar: bool,
+    /// Border around the chat pane with the current channel as its title
+    pub chat_border: bool,
+    /// Incoming messages per second above which a flood warning is shown
+    pub flood_threshold: usize,
+    /// Time without keyboard input after which the client logs out and exits
+    pub idle_logout: Option<Duration>,
+    pub coalesce_repeats: bool,
+    /// Show message timestamps in UTC instead of local time
+    pub timestamp_utc: bool,
+    /// Popup size in percent of the terminal
+    pub popup_width: u16,
+    pub popup_height: u16,
+    /// User-defined commands, `ALIAS_w=/tell` makes `/w` expand to `/tell`
+    pub aliases: HashMap<String, String>,
+}
+
+/// Name of the dimension `server` belongs to, if it is one of the official chat servers.
+pub fn dimension(server: &str) -> Option<&'static str> {
+    DIMENSIONS
+        .iter()
+        .find(|(address, _)| address.eq_ignore_ascii_case(server))
+        .map(|(_, name)| *name)
+}
+
+/// A `[name]` section of the config file, or the unnamed section before the first header.
+struct Section<'a> {
+    name: Option<&'a str>,
+    lines: Vec<&'a str>,
+}
+
+fn value<'a>(lines: &[&'a str], key: &str) -> Option<&'a str> {
+    lines
+        .iter()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
 }
 
-pub fn load(path: &Path) -> Option<Config> {
-    let contents = read_to_string(path).ok()?;
-    let character_name = contents
-        .lines()
-        .find(|line| line.starts_with("CHARNAME"))?
-        .split('=')
-        .nth(1)?;
-    let user_name = contents
-        .lines()
-        .find(|line| line.starts_with("USERNAME"))?
-        .split('=')
-        .nth(1)?;
-    let password = contents
-        .lines()
-        .find(|line| line.starts_with("PASSWORD"))?
-        .split('=')
-        .nth(1)?;
-
-    if character_name.is_empty() || user_name.is_empty() || password.is_empty() {
-        return None;
-    }
-
-    Some(Config {
+fn values<'a>(lines: &[&'a str], key: &str) -> Vec<&'a str> {
+    lines
+        .iter()
+        .filter_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+/// Collects `ALIAS_<name>=<expansion>` keys.
+fn aliases(lines: &[&str]) -> Vec<(String, String)> {
+    lines
+        .iter()
+        .filter_map(|line| line.strip_prefix("ALIAS_")?.split_once('='))
+        .filter(|(name, expansion)| !name.is_empty() && !expansion.is_empty())
+        .map(|(name, expansion)| (name.to_string(), expansion.to_string()))
+        .collect()
+}
+
+fn sections(contents: &str) -> Vec<Section<'_>> {
+    let mut sections = vec![Section {
+        name: None,
+        lines: Vec::new(),
+    }];
+
+    for line in contents.lines() {
+        if let Some(name) = line
+            .trim()
+            .strip_prefix('[')
+            .and_then(|l| l.strip_suffix(']'))
+        {
+            sections.push(Section {
+                name: Some(name),
+                lines: Vec::new(),
+            });
+        } else {
+            sections.last_mut().unwrap().lines.push(line);
+        }
+    }
+
+    sections
+}
+
+fn profile(name: &str, lines: &[&str], global: &[&str]) -> Result<Config, ConfigError> {
+    let get = |key: &str| value(lines, key).or_else(|| value(global, key));
+    let invalid = |key: &str| ConfigError::InvalidValue(key.to_string());
+    let required = |key: &str| match get(key) {
+        Some("") => Err(ConfigError::EmptyValue(key.to_string())),
+        Some(value) => Ok(value),
+        None => Err(ConfigError::MissingKey(key.to_string())),
+    };
+
+    let character_name = required("CHARNAME")?;
+    let user_name = required("USERNAME")?;
+    let password = required("PASSWORD")?;
+
+    let scrollback = match get("SCROLLBACK") {
+        Some(lines) if !lines.is_empty() => lines.parse().map_err(|_| invalid("SCROLLBACK"))?,
+        _ => DEFAULT_SCROLLBACK,
+    };
+    let theme = Theme::load(get).map_err(invalid)?;
+    let highlights = get("HIGHLIGHTS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect();
+    let autojoin = get("AUTOJOIN")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect();
+    let mut bell_on = BellTriggers::default();
+    for trigger in get("BELL_ON").unwrap_or_default().split(',') {
+        match trigger.trim() {
+            "tell" => bell_on.tell = true,
+            "highlight" => bell_on.highlight = true,
+            "" => {}
+            _ => return Err(invalid("BELL_ON")),
+        }
+    }
+    // Patterns may contain commas, so each one goes on its own line
+    let highlight_patterns = values(global, "HIGHLIGHT_REGEX")
+        .into_iter()
+        .chain(values(lines, "HIGHLIGHT_REGEX"))
+        .map(str::to_string)
+        .collect();
+
+    let flag = |key: &str, default: bool| match get(key).unwrap_or_default() {
+        "" => Ok(default),
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(invalid(key)),
+    };
+    let alt_screen = flag("ALT_SCREEN", true)?;
+    let percent = |key: &str, default: u16| match get(key) {
+        Some(value) if !value.is_empty() => value
+            .parse()
+            .ok()
+            .filter(|p| (1..=100).contains(p))
+            .ok_or_else(|| invalid(key)),
+        _ => Ok(default),
+    };
+    let seconds = |key: &str, default: u64| match get(key) {
+        Some(value) if !value.is_empty() => value
+            .parse()
+            .ok()
+            .filter(|s| *s > 0)
+            .map(Duration::from_secs)
+            .ok_or_else(|| invalid(key)),
+        _ => Ok(Duration::from_secs(default)),
+    };
+    let ping_interval = seconds("PING_INTERVAL", DEFAULT_PING_INTERVAL)?;
+    let ping_timeout = seconds("PING_TIMEOUT", DEFAULT_PING_TIMEOUT)?;
+    let popup_width = percent("POPUP_WIDTH", DEFAULT_POPUP_WIDTH)?;
+    let popup_height = percent("POPUP_HEIGHT", DEFAULT_POPUP_HEIGHT)?;
+    let focus_tells = flag("FOCUS_TELLS", false)?;
+    let focus_follow = flag("FOCUS_FOLLOW", false)?;
+    let return_to_chat = flag("RETURN_TO_CHAT_AFTER_COMMAND", false)?;
+    let minimal_statusbar = flag("MINIMAL_STATUSBAR", false)?;
+    let chat_border = flag("CHAT_BORDER", false)?;
+    let timestamp_utc = match get("TIMESTAMP_TZ").unwrap_or_default() {
+        "local" | "" => false,
+        "utc" => true,
+        _ => return Err(invalid("TIMESTAMP_TZ")),
+    };
+    let server = get("SERVER").filter(|server| !server.is_empty());
+    let direct_login = match get("LOGIN").unwrap_or_default() {
+        "seed" | "" => false,
+        // The live servers always send a seed, so this only makes sense elsewhere
+        "direct" if server.is_some() => true,
+        _ => return Err(invalid("LOGIN")),
+    };
+    let flood_threshold = match get("FLOOD_THRESHOLD") {
+        Some(count) if !count.is_empty() => {
+            count.parse().map_err(|_| invalid("FLOOD_THRESHOLD"))?
+        }
+        _ => DEFAULT_FLOOD_THRESHOLD,
+    };
+    let idle_logout = match get("IDLE_LOGOUT_MINUTES") {
+        Some(minutes) if !minutes.is_empty() => {
+            let seconds = minutes
+                .parse::<u64>()
+                .ok()
+                .and_then(|minutes| minutes.checked_mul(60))
+                .ok_or_else(|| invalid("IDLE_LOGOUT_MINUTES"))?;
+            Some(Duration::from_secs(seconds)).filter(|idle| !idle.is_zero())
+        }
+        _ => None,
+    };
+    let coalesce_repeats = flag("COALESCE_REPEATS", false)?;
+    let send_tag = get("SEND_TAG")
+        .filter(|tag| !tag.is_empty())
+        .unwrap_or(DEFAULT_SEND_TAG);
+    let decoration = Decoration {
+        prefix: get("MESSAGE_PREFIX").unwrap_or_default().to_string(),
+        suffix: get("MESSAGE_SUFFIX").unwrap_or_default().to_string(),
+    };
+    let defaults = ChannelPrefixes::default();
+    let prefix = |key: &str, default: String| {
+        get(key)
+            .filter(|prefix| !prefix.is_empty())
+            .map_or(default, str::to_string)
+    };
+    let prefixes = Arc::new(ChannelPrefixes {
+        group: prefix("PREFIX_GROUP", defaults.group),
+        org: prefix("PREFIX_ORG", defaults.org),
+        private_channel: prefix("PREFIX_PRIVATE", defaults.private_channel),
+        tell: prefix("PREFIX_TELL", defaults.tell),
+        vicinity: prefix("PREFIX_VICINITY", defaults.vicinity),
+    });
+    let control_socket = get("CONTROL_SOCKET")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from);
+
+    // Deliberately left out of the template, this is only meant for debugging
+    let packet_log = get("PACKET_LOG")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from);
+    let auto_responses = get("AUTO_RESPONSES")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from);
+
+    // Aliases of the profile take precedence over global ones with the same name
+    let aliases = aliases(global).into_iter().chain(aliases(lines)).collect();
+
+    Ok(Config {
+        profile: name.to_string(),
         user_name: user_name.to_string(),
         character_name: character_name.to_string(),
         password: password.to_string(),
+        scrollback,
+        theme,
+        highlights,
+        autojoin,
+        highlight_patterns,
+        bell_on,
+        control_socket,
+        packet_log,
+        auto_responses,
+        alt_screen,
+        send_tag: send_tag.to_string(),
+        decoration,
+        prefixes,
+        server: server.unwrap_or(DEFAULT_SERVER).to_string(),
+        direct_login,
+        ping_interval,
+        ping_timeout,
+        focus_tells,
+        focus_follow,
+        return_to_chat,
+        minimal_statusbar,
+        chat_border,
+        flood_threshold,
+        idle_logout,
+        coalesce_repeats,
+        timestamp_utc,
+        popup_width,
+        popup_height,
+        aliases,
     })
 }
+
+/// Loads all profiles from the config file. Keys outside of a `[name]` section
+/// form the default profile, or act as fallbacks if named profiles exist.
+pub fn load(path: &Path) -> Result<Vec<Config>, ConfigError> {
+    parse(&read_to_string(path)?)
+}
+
+/// Parses all profiles from the contents of a config file.
+pub fn parse(contents: &str) -> Result<Vec<Config>, ConfigError> {
+    let sections = sections(contents);
+    let global = &sections[0].lines;
+
+    if sections.len() == 1 {
+        return Ok(vec![profile(DEFAULT_PROFILE, global, &[])?]);
+    }
+
+    sections
+        .iter()
+        .filter_map(|section| Some((section.name?, &section.lines)))
+        .map(|(name, lines)| {
+            profile(name, lines, global)
+                .map_err(|e| ConfigError::Profile(name.to_string(), Box::new(e)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINIMAL: &str = "USERNAME=user\nPASSWORD=secret\nCHARNAME=Tester\n";
+
+    fn error(contents: &str) -> String {
+        match parse(contents) {
+            Ok(_) => panic!("accepted {:?}", contents),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn flags_only_accept_true_or_false() {
+        let config = parse(&format!("{}CHAT_BORDER=true\nALT_SCREEN=false\n", MINIMAL))
+            .unwrap()
+            .remove(0);
+        assert!(config.chat_border);
+        assert!(!config.alt_screen);
+
+        assert_eq!(
+            error(&format!("{}CHAT_BORDER=yes\n", MINIMAL)),
+            "CHAT_BORDER has an invalid value"
+        );
+    }
+
+    #[test]
+    fn errors_name_the_profile() {
+        assert_eq!(
+            error(&format!("{}[main]\n[alt]\nFOCUS_TELLS=1\n", MINIMAL)),
+            "Profile alt: FOCUS_TELLS has an invalid value"
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    fs::{remove_file, symlink_metadata},
+    io,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::mpsc::UnboundedSender,
+};
+
+use crate::{
+    chat::{Command, ResolvedChannel, UiUpdate},
+    command,
+    config::ChannelPrefixes,
+};
+
+/// Accepts line-delimited `/`-commands on a Unix socket and forwards them to the chat task.
+pub async fn control_task(
+    path: PathBuf,
+    command_sender: UnboundedSender<Command>,
+    ui_update_sender: UnboundedSender<UiUpdate>,
+    read_only: bool,
+    prefixes: Arc<ChannelPrefixes>,
+) {
+    let listener = match remove_stale_socket(&path).and_then(|()| UnixListener::bind(&path)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            let _ = ui_update_sender.send(UiUpdate::System(format!(
+                "Could not open control socket {}: {}",
+                path.display(),
+                e
+            )));
+            return;
+        }
+    };
+
+    while let Ok((stream, _)) = listener.accept().await {
+        let command_sender = command_sender.clone();
+        let prefixes = prefixes.clone();
+        tokio::spawn(async move {
+            let _ = handle_connection(stream, &command_sender, read_only, &prefixes).await;
+        });
+    }
+}
+
+/// Removes a socket left over from a previous run, which would make binding fail.
+/// Anything else at `path` is left alone, in case the path was mistyped.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => remove_file(path),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "a file that is not a socket is in the way",
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+async fn handle_connection(
+    stream: UnixStream,
+    command_sender: &UnboundedSender<Command>,
+    read_only: bool,
+    prefixes: &Arc<ChannelPrefixes>,
+) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let reply = match parse(&line, read_only, prefixes) {
+            Ok(cmd) => {
+                if command_sender.send(cmd).is_ok() {
+                    String::from("ok\n")
+                } else {
+                    String::from("error: not connected\n")
+                }
+            }
+            Err(e) => format!("error: {}\n", e),
+        };
+        writer.write_all(reply.as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+fn parse(
+    line: &str,
+    read_only: bool,
+    prefixes: &Arc<ChannelPrefixes>,
+) -> Result<Command, &'static str> {
+    let cmd = command::Command::from_input(line.trim()).ok_or("invalid command")?;
+
+    if read_only && !cmd.is_read_only() {
+        return Err("read-only mode");
+    }
+    // There is no current channel to fall back to here
+    if cmd.has_implicit_target() {
+        return Err("a character name is required");
+    }
+
+    Command::new(cmd, &ResolvedChannel::vicinity(prefixes))
+        .ok_or("not available over the control socket")
+}
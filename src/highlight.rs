@@ -0,0 +1,77 @@
+use std::ops::Range;
+
+use regex::Regex;
+
+/// Finds the parts of message text that should be highlighted or shown as links.
+pub struct Highlighter {
+    patterns: Vec<Regex>,
+    links: Regex,
+}
+
+impl Highlighter {
+    /// Compiles case-insensitive substring `words` and regex `patterns`. Patterns that
+    /// fail to compile are skipped and described in the returned errors.
+    pub fn new(words: &[String], patterns: &[String]) -> (Self, Vec<String>) {
+        let mut errors = Vec::new();
+        let mut compiled: Vec<Regex> = words
+            .iter()
+            .filter_map(|word| Regex::new(&format!("(?i){}", regex::escape(word))).ok())
+            .collect();
+
+        for pattern in patterns {
+            match Regex::new(pattern) {
+                Ok(regex) => compiled.push(regex),
+                Err(e) => errors.push(format!("Invalid highlight regex {}: {}", pattern, e)),
+            }
+        }
+
+        let links = Regex::new(r#"https?://[^\s<>"']+"#).unwrap();
+
+        (
+            Self {
+                patterns: compiled,
+                links,
+            },
+            errors,
+        )
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.patterns.iter().any(|regex| regex.is_match(text))
+    }
+
+    /// Returns the sorted, non-overlapping byte ranges of `text` to highlight.
+    pub fn find(&self, text: &str) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = self
+            .patterns
+            .iter()
+            .flat_map(|regex| regex.find_iter(text).map(|m| m.range()))
+            .filter(|range| !range.is_empty())
+            .collect();
+        ranges.sort_by_key(|range| range.start);
+
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+
+        merged
+    }
+
+    /// Returns the sorted byte ranges of http(s) URLs in `text`. Trailing punctuation
+    /// is left out, as it usually ends the sentence rather than the URL.
+    pub fn find_links(&self, text: &str) -> Vec<Range<usize>> {
+        self.links
+            .find_iter(text)
+            .map(|m| {
+                let url = m
+                    .as_str()
+                    .trim_end_matches(&['.', ',', ')', '!', '?', ':', ';'][..]);
+                m.start()..m.start() + url.len()
+            })
+            .collect()
+    }
+}
@@ -1,8 +1,23 @@
 pub use crossterm::event::*;
 
-pub fn should_quit(input: &Event) -> bool {
-    matches!(input, Event::Key(KeyEvent {
-                            code: KeyCode::Char('c' | 'd'),
-                            modifiers,
-                        }) if modifiers.contains(KeyModifiers::CONTROL))
+pub enum QuitKey {
+    /// Ctrl+C
+    Interrupt,
+    /// Ctrl+D
+    EndOfFile,
+}
+
+/// Checks whether `input` asks to quit. In Scroll mode Ctrl+D scrolls instead.
+pub fn quit_key(input: &Event, scrolling: bool) -> Option<QuitKey> {
+    match input {
+        Event::Key(KeyEvent {
+            code: KeyCode::Char('c'),
+            modifiers,
+        }) if modifiers.contains(KeyModifiers::CONTROL) => Some(QuitKey::Interrupt),
+        Event::Key(KeyEvent {
+            code: KeyCode::Char('d'),
+            modifiers,
+        }) if modifiers.contains(KeyModifiers::CONTROL) && !scrolling => Some(QuitKey::EndOfFile),
+        _ => None,
+    }
 }
@@ -0,0 +1,127 @@
+use std::io::{self, Write};
+
+use serde_json::{json, Value};
+use tokio::sync::mpsc::unbounded_channel;
+
+use crate::{
+    chat::{self, UiUpdate},
+    config::Config,
+    lists::{ChannelAliases, WordList},
+    transport,
+};
+
+/// Runs without the TUI, printing messages and connection events to stdout as one
+/// JSON object per line. Commands can still be sent through the control socket.
+pub async fn run(
+    config: Config,
+    read_only: bool,
+    ignored: WordList,
+    highlights: WordList,
+    channel_aliases: ChannelAliases,
+) -> io::Result<()> {
+    // Nothing queries state here, but the chat task stops once every sender is gone
+    let (_state_query_sender, state_query_receiver) = unbounded_channel();
+    let (command_sender, command_receiver) = unbounded_channel();
+    let (ui_update_sender, mut ui_update_receiver) = unbounded_channel();
+    #[cfg(unix)]
+    if let Some(path) = config.control_socket.clone() {
+        tokio::spawn(crate::control::control_task(
+            path,
+            command_sender.clone(),
+            ui_update_sender.clone(),
+            read_only,
+            config.prefixes.clone(),
+        ));
+    }
+    tokio::spawn(chat::chat_task(
+        transport::AoConnector,
+        state_query_receiver,
+        command_receiver,
+        ui_update_sender,
+        config,
+        read_only,
+        ignored,
+        highlights,
+        channel_aliases,
+    ));
+
+    let mut stdout = io::stdout();
+    let mut failed = false;
+    while let Some(update) = ui_update_receiver.recv().await {
+        // The control socket keeps the channel open, so the loop has to end here
+        failed = matches!(update, UiUpdate::Fatal(_));
+        if let Some(event) = event(update) {
+            writeln!(stdout, "{}", event)?;
+            stdout.flush()?;
+        }
+        if failed {
+            break;
+        }
+    }
+    drop(command_sender);
+    if failed {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// The JSON form of an update, or `None` for updates that only matter to the TUI.
+fn event(update: UiUpdate) -> Option<Value> {
+    let event = match update {
+        UiUpdate::Message(msg) => json!({
+            "type": "message",
+            "time": msg.timestamp.to_rfc3339(),
+            "channel": msg.channel.render(),
+            "channel_type": msg.channel.r#type.describe(),
+            "sender": msg.sender,
+            "text": msg.text,
+            "outgoing": msg.outgoing,
+        }),
+        UiUpdate::Connection(state) => json!({
+            "type": "connection",
+            "state": state.describe(),
+        }),
+        UiUpdate::Reconnected => json!({
+            "type": "reconnected",
+        }),
+        UiUpdate::Invite(channel) => json!({
+            "type": "invite",
+            "channel": channel.render(),
+        }),
+        UiUpdate::Kick(user, channel) => json!({
+            "type": "kick",
+            "user": user,
+            "channel": channel.render(),
+        }),
+        UiUpdate::Leave(channel) => json!({
+            "type": "leave",
+            "channel": channel.render(),
+        }),
+        UiUpdate::Kicked(channel) => json!({
+            "type": "kicked",
+            "channel": channel.render(),
+        }),
+        UiUpdate::Fatal(reason) => json!({
+            "type": "fatal",
+            "reason": reason,
+        }),
+        UiUpdate::System(text) => json!({
+            "type": "system",
+            "text": text,
+        }),
+        UiUpdate::Status(text) => json!({
+            "type": "status",
+            "text": text,
+        }),
+        UiUpdate::Closed(_)
+        | UiUpdate::Channels(_)
+        | UiUpdate::Latency(_)
+        | UiUpdate::Delivery(..)
+        | UiUpdate::Highlights(_)
+        | UiUpdate::Muted(_)
+        | UiUpdate::TellSent(_) => return None,
+    };
+
+    Some(event)
+}
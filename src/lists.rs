@@ -0,0 +1,97 @@
+use std::{
+    collections::BTreeMap,
+    fs::{read_to_string, write},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// A list of names or words that is changed through commands and stored as a JSON
+/// array, separately from the static config file.
+pub struct WordList {
+    path: PathBuf,
+    words: Vec<String>,
+}
+
+impl WordList {
+    /// Loads the list from `path`, starting out empty if the file is missing or invalid.
+    pub fn load(path: PathBuf) -> Self {
+        let words = read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+
+        Self { path, words }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn words(&self) -> &[String] {
+        &self.words
+    }
+
+    pub fn contains(&self, word: &str) -> bool {
+        self.words.iter().any(|w| w.eq_ignore_ascii_case(word))
+    }
+
+    /// Adds `word` and saves the list. Returns whether it was not in the list yet.
+    pub fn add(&mut self, word: &str) -> io::Result<bool> {
+        if self.contains(word) {
+            return Ok(false);
+        }
+        self.words.push(word.to_string());
+        self.flush()?;
+        Ok(true)
+    }
+
+    /// Removes `word` and saves the list. Returns whether it was in the list.
+    pub fn remove(&mut self, word: &str) -> io::Result<bool> {
+        if !self.contains(word) {
+            return Ok(false);
+        }
+        self.words.retain(|w| !w.eq_ignore_ascii_case(word));
+        self.flush()?;
+        Ok(true)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        write(&self.path, serde_json::to_string_pretty(&self.words)?)
+    }
+}
+
+/// Local display names for channels, stored as a JSON object from the rendered real
+/// name of a channel, like `#Owner`, to its alias.
+pub struct ChannelAliases {
+    path: PathBuf,
+    aliases: BTreeMap<String, String>,
+}
+
+impl ChannelAliases {
+    /// Loads the aliases from `path`, starting out empty if the file is missing or invalid.
+    pub fn load(path: PathBuf) -> Self {
+        let aliases = read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+
+        Self { path, aliases }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get(&self, channel: &str) -> Option<&str> {
+        self.aliases.get(channel).map(String::as_str)
+    }
+
+    /// Sets or, with `None`, removes the alias of `channel` and saves the aliases.
+    pub fn set(&mut self, channel: &str, alias: Option<&str>) -> io::Result<()> {
+        match alias {
+            Some(alias) => self.aliases.insert(channel.to_string(), alias.to_string()),
+            None => self.aliases.remove(channel),
+        };
+        write(&self.path, serde_json::to_string_pretty(&self.aliases)?)
+    }
+}
@@ -3,70 +3,1045 @@
     clippy::let_underscore_drop,
     clippy::cast_possible_truncation,
     clippy::too_many_lines,
-    clippy::module_name_repetitions
+    clippy::module_name_repetitions,
+    clippy::struct_excessive_bools
 )]
 
-use chat::{ChannelType, ResolvedChannel};
+use chat::{
+    ChannelType, ConnectionState, Delivery, RenderOptions, ResolvedChannel, ResolvedMessage,
+};
+use chrono::{DateTime, Local, NaiveDate};
 use directories::ProjectDirs;
 use futures_util::StreamExt;
-use nadylib::{AOSocket, SocketConfig};
-use tokio::sync::{mpsc::unbounded_channel, oneshot};
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use tokio::{
+    sync::{
+        mpsc::{unbounded_channel, UnboundedSender},
+        oneshot,
+    },
+    time::{interval, timeout},
+};
 use tui::{
     layout::{Alignment, Constraint, Direction, Layout},
     style::{Color, Modifier, Style},
-    text::Text,
+    text::{Span, Spans},
     widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap},
 };
+use unicode_width::UnicodeWidthStr;
 
 use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
     fs::{create_dir_all, write},
-    io,
+    io::{self, Write},
+    ops::RangeInclusive,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
-use crate::chat::{Command, StateQuery, UiUpdate};
+use crate::{
+    chat::{Command, Diagnostics, StateQuery, UiUpdate, MESSAGE_BYTE_LIMIT},
+    command::{CommandInfo, Toggle},
+    config::{BellTriggers, ChannelPrefixes, Config, Decoration},
+    highlight::Highlighter,
+    lists::{ChannelAliases, WordList},
+    theme::{ColorSupport, Theme},
+};
 
 mod chat;
 mod command;
 mod config;
+#[cfg(unix)]
+mod control;
+mod highlight;
 mod input;
+mod json;
+mod lists;
+mod markup;
+mod packet_log;
+mod responses;
 mod term;
+mod theme;
+mod transport;
 mod util;
 
-const ORANGE: Color = Color::Rgb(232, 149, 6);
+const STATE_QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+const CLOCK_INTERVAL: Duration = Duration::from_secs(1);
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+const SWITCHER_HIGHLIGHT: &str = ">>";
+const POPUP_MIN_WIDTH: u16 = 30;
+const POPUP_MIN_HEIGHT: u16 = 8;
+const BELL_DEBOUNCE: Duration = Duration::from_secs(3);
+const QUIT_CONFIRM_WINDOW: Duration = Duration::from_secs(2);
+/// How long a status message stays in the status bar
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+/// Period over which incoming messages are counted for the flood warning
+const FLOOD_WINDOW: Duration = Duration::from_secs(1);
+/// Characters of a message kept by `/quote`
+const QUOTE_LENGTH: usize = 40;
 
-#[derive(PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 enum InputMode {
     Command,
     Chat,
+    /// Browsing the scrollback. Besides the arrow and page keys, this takes vi-style
+    /// `j`/`k` for lines, `g`/`G` for oldest/newest and Ctrl+U/Ctrl+D for half pages.
+    /// `l` lists where the links in the selected messages point to.
     Scroll,
 }
 
+impl InputMode {
+    /// Glyph shown at the start of the input bar.
+    fn prompt(self) -> &'static str {
+        match self {
+            Self::Command => ":",
+            Self::Chat => ">",
+            Self::Scroll => "^",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Command => "Command",
+            Self::Chat => "Chat",
+            Self::Scroll => "Scroll",
+        }
+    }
+
+    /// Shown instead of the name by the minimal status bar.
+    fn indicator(self) -> char {
+        match self {
+            Self::Command => '/',
+            Self::Chat => 'C',
+            Self::Scroll => 'S',
+        }
+    }
+}
+
+/// Something in the scrollback, kept so it can be rendered again with other options.
+enum Entry {
+    /// A chat message, and whether it continues the previous one in compact mode
+    Message(ResolvedMessage, bool),
+    System(String, DateTime<Local>),
+    Separator(NaiveDate),
+    /// Where the session was picked up again after a reconnect
+    Reconnected(DateTime<Local>),
+}
+
 struct App<'a> {
     current_mode: InputMode,
     channel_switcher_open: bool,
     channel_switcher_state: ListState,
     channel_switcher_channels: Vec<ResolvedChannel>,
+    channel_switcher_filter: String,
+    /// Diagnostics overlay, refreshed every second while open
+    diagnostics: Option<Diagnostics>,
+    /// Command palette, listing the built-in commands
+    palette_open: bool,
+    palette_state: ListState,
+    palette_filter: String,
     current_channel: ResolvedChannel,
     input_text: String,
+    /// Draft of the mode that is currently not active, swapped in on mode changes
+    other_draft: String,
+    /// Transient status, shown until `STATUS_TIMEOUT` after it was set
     status_text: String,
-    messages: Text<'a>,
+    status_set: Instant,
+    /// Character name, shown next to the connection state for the whole session
+    character_name: String,
+    /// Rendered chat lines, newest first
+    lines: VecDeque<Spans<'a>>,
+    /// What `lines` was rendered from, newest first
+    entries: VecDeque<Entry>,
+    render_options: RenderOptions,
     scroll_y: usize,
+    /// Mode to return to when leaving Scroll mode
+    mode_before_scroll: InputMode,
+    /// Height of the chat pane as of the last draw
+    chat_height: usize,
+    /// Width of the chat pane as of the last draw, to tell how lines wrap
+    chat_width: usize,
+    scrollback: usize,
+    read_only: bool,
+    latency: Option<Duration>,
+    connection: ConnectionState,
+    theme: Theme,
+    highlighter: Highlighter,
+    /// Highlight words and patterns from the config, combined with the runtime words
+    config_highlights: Vec<String>,
+    highlight_patterns: Vec<String>,
+    compact: bool,
+    bell_on: BellTriggers,
+    focus_tells: bool,
+    focus_follow: bool,
+    return_to_chat: bool,
+    /// Color only a one-character mode indicator in the status bar
+    minimal_statusbar: bool,
+    chat_border: bool,
+    popup_width: u16,
+    popup_height: u16,
+    aliases: HashMap<String, String>,
+    /// Channels muted in the chat task, tagged in the switcher
+    muted: HashSet<(ChannelType, u32)>,
+    /// Private channel topics set during this session, by channel id
+    topics: HashMap<u32, String>,
+    last_bell: Option<Instant>,
+    last_message: Option<(Option<String>, String)>,
+    /// Text of the last message that was confirmed sent, for Ctrl+Y
+    last_sent: Option<String>,
+    /// Text last submitted to each channel, brought back with Up to fix typos
+    submitted: HashMap<(ChannelType, u32), String>,
+    /// Id for the next chat message shown, counting from 1
+    next_message_id: u64,
+    /// Added to chat messages by the chat task, counted towards the length limit
+    decoration: Decoration,
+    /// Symbols channel names are shown with
+    prefixes: Arc<ChannelPrefixes>,
+    /// Day of the last chat message, to separate days in the scrollback
+    last_date: Option<NaiveDate>,
+    /// Total number of lines ever pushed, used to locate lines after newer ones were prepended
+    pushed_lines: usize,
+    /// Value of `pushed_lines` right after each pending echo was pushed
+    pending_echoes: HashMap<u64, usize>,
+    /// Selected lines in Scroll mode, as anchor and cursor indices into `lines`
+    selection: Option<(usize, usize)>,
+    flood_threshold: usize,
+    /// Arrival times of incoming messages within the last `FLOOD_WINDOW`
+    recent_messages: VecDeque<Instant>,
+    coalesce_repeats: bool,
 }
 
-#[tokio::main]
-async fn main() -> io::Result<()> {
-    let project_dirs = ProjectDirs::from("org", "Nadybot", "ao-chat-client")
-        .expect("No valid home directory path provided by OS");
-    let mut config_path = project_dirs.config_dir().to_path_buf();
+impl<'a> App<'a> {
+    fn new(config: &Config, read_only: bool, highlighter: Highlighter) -> Self {
+        Self {
+            current_mode: InputMode::Command,
+            channel_switcher_open: false,
+            channel_switcher_state: ListState::default(),
+            channel_switcher_channels: Vec::new(),
+            channel_switcher_filter: String::new(),
+            diagnostics: None,
+            palette_open: false,
+            palette_state: ListState::default(),
+            palette_filter: String::new(),
+            current_channel: ResolvedChannel::vicinity(&config.prefixes),
+            input_text: String::new(),
+            other_draft: String::new(),
+            status_text: String::from("Initialized"),
+            status_set: Instant::now(),
+            character_name: config.character_name.clone(),
+            lines: VecDeque::new(),
+            entries: VecDeque::new(),
+            render_options: RenderOptions {
+                utc: config.timestamp_utc,
+                ..RenderOptions::default()
+            },
+            scroll_y: 0,
+            mode_before_scroll: InputMode::Command,
+            chat_height: 0,
+            chat_width: 0,
+            scrollback: config.scrollback,
+            read_only,
+            latency: None,
+            connection: ConnectionState::Connecting,
+            theme: config.theme.reduced(ColorSupport::detect()),
+            highlighter,
+            config_highlights: config.highlights.clone(),
+            highlight_patterns: config.highlight_patterns.clone(),
+            compact: false,
+            bell_on: config.bell_on,
+            focus_tells: config.focus_tells,
+            focus_follow: config.focus_follow,
+            return_to_chat: config.return_to_chat,
+            minimal_statusbar: config.minimal_statusbar,
+            chat_border: config.chat_border,
+            popup_width: config.popup_width,
+            popup_height: config.popup_height,
+            aliases: config.aliases.clone(),
+            muted: HashSet::new(),
+            topics: HashMap::new(),
+            last_bell: None,
+            last_message: None,
+            last_sent: None,
+            submitted: HashMap::new(),
+            next_message_id: 1,
+            decoration: config.decoration.clone(),
+            prefixes: config.prefixes.clone(),
+            last_date: None,
+            pushed_lines: 0,
+            pending_echoes: HashMap::new(),
+            selection: None,
+            flood_threshold: config.flood_threshold,
+            recent_messages: VecDeque::new(),
+            coalesce_repeats: config.coalesce_repeats,
+        }
+    }
 
-    if !config_path.exists() {
-        create_dir_all(&config_path)?;
+    /// Rings the terminal bell, unless it already rang recently.
+    fn ring_bell(&mut self) {
+        if matches!(self.last_bell, Some(last) if last.elapsed() < BELL_DEBOUNCE) {
+            return;
+        }
+        self.last_bell = Some(Instant::now());
+
+        let mut stdout = io::stdout();
+        if let Err(e) = stdout.write_all(b"\x07").and_then(|()| stdout.flush()) {
+            self.set_status(format!("Could not ring the bell: {}", e));
+        }
+    }
+
+    fn set_status(&mut self, text: impl Into<String>) {
+        self.status_text = text.into();
+        self.status_set = Instant::now();
+    }
+
+    /// The status message, or nothing once it expired.
+    fn status(&self) -> &str {
+        if self.status_set.elapsed() < STATUS_TIMEOUT {
+            &self.status_text
+        } else {
+            ""
+        }
+    }
+
+    fn push_system(&mut self, text: &str) {
+        self.last_message = None;
+        self.push_entry(Entry::System(text.to_string(), Local::now()));
+    }
+
+    fn render_entry(&self, entry: &Entry) -> Vec<Spans<'a>> {
+        match entry {
+            Entry::Message(msg, false) => {
+                msg.render(&self.theme, &self.highlighter, self.render_options)
+            }
+            Entry::Message(msg, true) => {
+                msg.render_continuation(&self.theme, &self.highlighter, self.render_options)
+            }
+            Entry::System(text, time) => {
+                chat::render_system_message(text, *time, &self.theme, self.render_options)
+            }
+            Entry::Reconnected(time) => vec![Spans::from(Span::styled(
+                format!(
+                    "──── {}reconnected ────",
+                    self.render_options.timestamp(*time)
+                ),
+                Style::default().fg(self.theme.system),
+            ))],
+            Entry::Separator(date) => vec![Spans::from(Span::styled(
+                format!("──── {} ────", date.format("%Y-%m-%d")),
+                Style::default().fg(self.theme.system),
+            ))],
+        }
+    }
+
+    fn push_entry(&mut self, entry: Entry) {
+        let lines = self.render_entry(&entry);
+        self.entries.push_front(entry);
+        self.entries.truncate(self.scrollback);
+        self.push_lines(lines);
+    }
+
+    /// Renders the whole scrollback again, after the render options changed.
+    fn rerender(&mut self) {
+        // Every entry keeps its line count, so pending echoes can still be found
+        let lines: VecDeque<Spans<'a>> = self
+            .entries
+            .iter()
+            .flat_map(|entry| self.render_entry(entry))
+            .take(self.scrollback)
+            .collect();
+        self.lines = lines;
+    }
+
+    fn toggle(&mut self, toggle: &Toggle) {
+        let (name, enabled) = match toggle {
+            Toggle::Timestamps => {
+                self.render_options.timestamps = !self.render_options.timestamps;
+                ("Timestamps", self.render_options.timestamps)
+            }
+            Toggle::Prefixes => {
+                self.render_options.channels = !self.render_options.channels;
+                ("Channel prefixes", self.render_options.channels)
+            }
+            Toggle::Ids => {
+                self.render_options.ids = !self.render_options.ids;
+                ("Message ids", self.render_options.ids)
+            }
+            Toggle::Follow => {
+                self.focus_follow = !self.focus_follow;
+                ("Focus follow", self.focus_follow)
+            }
+        };
+        if !matches!(toggle, Toggle::Follow) {
+            self.rerender();
+        }
+        self.set_status(format!("{} {}", name, if enabled { "on" } else { "off" }));
+    }
+
+    /// Channels matching the switcher filter, best match first, along with the
+    /// indices of the matched characters in their rendered names.
+    fn switcher_matches(&self) -> Vec<(&ResolvedChannel, Vec<usize>)> {
+        if self.channel_switcher_filter.is_empty() {
+            return self
+                .channel_switcher_channels
+                .iter()
+                .map(|c| (c, Vec::new()))
+                .collect();
+        }
+
+        let matcher = SkimMatcherV2::default();
+        let mut matches: Vec<(i64, &ResolvedChannel, Vec<usize>)> = self
+            .channel_switcher_channels
+            .iter()
+            .filter_map(|c| {
+                let (score, indices) =
+                    matcher.fuzzy_indices(&switcher_label(c), &self.channel_switcher_filter)?;
+                Some((score, c, indices))
+            })
+            .collect();
+        matches.sort_by_key(|(score, _, _)| Reverse(*score));
+
+        matches
+            .into_iter()
+            .map(|(_, c, indices)| (c, indices))
+            .collect()
+    }
+
+    /// Commands matching the palette filter, best match first, along with the indices
+    /// of the matched characters in their names.
+    fn palette_matches(&self) -> Vec<(&'static CommandInfo, Vec<usize>)> {
+        let matcher = SkimMatcherV2::default();
+        let mut matches: Vec<(i64, &CommandInfo, Vec<usize>)> = command::COMMANDS
+            .iter()
+            .filter_map(|c| {
+                let (score, indices) = matcher.fuzzy_indices(c.name, &self.palette_filter)?;
+                Some((score, c, indices))
+            })
+            .collect();
+        // Keep the listed order while nothing is typed
+        if !self.palette_filter.is_empty() {
+            matches.sort_by_key(|(score, _, _)| Reverse(*score));
+        }
+
+        matches
+            .into_iter()
+            .map(|(_, c, indices)| (c, indices))
+            .collect()
+    }
+
+    fn toggle_palette(&mut self) {
+        self.palette_open = !self.palette_open;
+        if self.palette_open {
+            self.channel_switcher_open = false;
+            self.palette_filter.clear();
+            self.palette_state.select(None);
+        }
+    }
+
+    /// Starts typing `command` in Command mode, keeping the chat draft.
+    fn pick_command(&mut self, command: &CommandInfo) {
+        if self.current_mode == InputMode::Chat {
+            std::mem::swap(&mut self.input_text, &mut self.other_draft);
+        }
+        self.current_mode = InputMode::Command;
+        self.input_text = format!("/{}", command.name);
+        if !command.args.is_empty() {
+            self.input_text.push(' ');
+        }
+        self.palette_open = false;
+        self.set_status(command.usage());
+    }
+
+    /// Writes the scrollback as plain text, oldest line first.
+    fn export(&mut self, path: &str) {
+        let text: String = self
+            .lines
+            .iter()
+            .rev()
+            .map(|line| {
+                let mut plain: String = line.0.iter().map(|span| span.content.as_ref()).collect();
+                plain.push('\n');
+                plain
+            })
+            .collect();
+
+        let status = match write(path, text) {
+            Ok(()) => format!("Exported {} lines to {}", self.lines.len(), path),
+            Err(e) => format!("Failed to export to {}: {}", path, e),
+        };
+        self.set_status(status);
+    }
+
+    /// The lines that fit in the chat pane at the current scroll position, with the
+    /// selection reversed. Only these are cloned for a frame, not the whole scrollback.
+    fn visible_lines(&self) -> Vec<Spans<'a>> {
+        let selected = self.selected_lines();
+        self.lines
+            .iter()
+            .enumerate()
+            .skip(self.scroll_y)
+            .take(self.chat_height)
+            .map(|(i, line)| match &selected {
+                Some(range) if range.contains(&i) => line
+                    .0
+                    .iter()
+                    .map(|span| {
+                        Span::styled(
+                            span.content.clone(),
+                            span.style.add_modifier(Modifier::REVERSED),
+                        )
+                    })
+                    .collect::<Vec<Span>>()
+                    .into(),
+                _ => line.clone(),
+            })
+            .collect()
+    }
+
+    /// Lines covered by the selection, as indices into `lines`.
+    fn selected_lines(&self) -> Option<RangeInclusive<usize>> {
+        self.selection
+            .map(|(anchor, cursor)| anchor.min(cursor)..=anchor.max(cursor))
+    }
+
+    /// Starts a selection at the top visible line, or moves its cursor one line
+    /// towards older lines if `older` and newer ones otherwise.
+    fn extend_selection(&mut self, older: bool) {
+        let (anchor, cursor) = match self.selection {
+            Some((anchor, cursor)) if older => {
+                (anchor, (cursor + 1).min(self.lines.len().saturating_sub(1)))
+            }
+            Some((anchor, cursor)) => (anchor, cursor.saturating_sub(1)),
+            None => (self.scroll_y, self.scroll_y),
+        };
+        self.selection = Some((anchor, cursor));
+
+        // Keep the cursor on screen
+        if cursor < self.scroll_y {
+            self.scroll_y = cursor;
+        } else if cursor >= self.scroll_y + self.chat_height {
+            self.scroll_y = cursor + 1 - self.chat_height;
+        }
+    }
+
+    /// Copies the selected lines as plain text, oldest line first. The terminal puts
+    /// them on the clipboard through an OSC 52 sequence, which also works over SSH.
+    fn copy_selection(&mut self) {
+        if let Some(range) = self.selected_lines() {
+            let text = self
+                .lines
+                .range(range.clone())
+                .rev()
+                .map(|line| line.0.iter().map(|span| span.content.as_ref()).collect())
+                .collect::<Vec<String>>()
+                .join("\n");
+
+            let mut stdout = io::stdout();
+            let copied = write!(stdout, "\x1b]52;c;{}\x07", util::base64(text.as_bytes()))
+                .and_then(|()| stdout.flush());
+
+            match copied {
+                Ok(()) => self.set_status(format!("Copied {} lines", range.count())),
+                Err(e) => self.set_status(format!("Could not copy the selection: {}", e)),
+            }
+            self.selection = None;
+        }
+    }
+
+    /// Lists the targets of the markup links in the selected messages, or in the
+    /// message at the top of the view without a selection.
+    fn show_links(&mut self) {
+        let range = self
+            .selected_lines()
+            .unwrap_or(self.scroll_y..=self.scroll_y);
+        let mut links = Vec::new();
+        let mut first_line = 0;
+
+        for entry in &self.entries {
+            if first_line > *range.end() {
+                break;
+            }
+            let line_count = self.render_entry(entry).len();
+            if let Entry::Message(msg, _) = entry {
+                if first_line + line_count > *range.start() {
+                    links.extend(msg.links.iter().map(|link| {
+                        format!("{}: {}", &msg.text[link.range.clone()], link.describe())
+                    }));
+                }
+            }
+            first_line += line_count;
+        }
+
+        if links.is_empty() {
+            self.set_status("No links in the selected messages");
+        } else {
+            self.push_system(&links.join("\n"));
+        }
+    }
+
+    /// Whether chat messages can be sent. Says why not in the status bar otherwise.
+    /// While disconnected, messages are queued by the chat task.
+    fn can_send(&mut self) -> bool {
+        if self.read_only {
+            self.set_status("Read-only mode, nothing was sent");
+        }
+        !self.read_only
+    }
+
+    /// Swaps the delivery marker on the first line of a pending echo.
+    fn update_delivery(&mut self, id: u64, delivery: Delivery) {
+        for entry in &mut self.entries {
+            if let Entry::Message(msg, _) = entry {
+                if matches!(msg.delivery, Some((echo_id, _)) if echo_id == id) {
+                    msg.delivery = Some((id, delivery));
+                    if delivery == Delivery::Sent {
+                        self.last_sent = Some(self.decoration.strip(&msg.text).to_string());
+                    }
+                    break;
+                }
+            }
+        }
+
+        if let Some(pushed_at) = self.pending_echoes.remove(&id) {
+            let index = self.pushed_lines - pushed_at;
+            if let Some(span) = self
+                .lines
+                .get_mut(index)
+                .and_then(|line| line.0.first_mut())
+            {
+                span.content = delivery.marker().into();
+            }
+        }
     }
 
-    config_path.push("config.txt");
+    /// Shows or sets the topic of the current private channel. The protocol has no
+    /// topic packets, so topics only live for this session.
+    fn topic(&mut self, text: Option<String>) {
+        if self.current_channel.r#type != ChannelType::PrivateChannel {
+            self.set_status(String::from("Topics only exist for private channels"));
+            return;
+        }
+
+        let channel = self.current_channel.render();
+        let line = match text {
+            Some(text) => {
+                let line = format!("Local topic for {} (not shared): {}", channel, text);
+                self.topics.insert(self.current_channel.id, text);
+                line
+            }
+            None => match self.topics.get(&self.current_channel.id) {
+                Some(topic) => format!("Local topic for {} (not shared): {}", channel, topic),
+                None => format!("No local topic for {}", channel),
+            },
+        };
+        self.push_system(&line);
+    }
+
+    /// Puts the text last submitted to the current channel back into the empty chat
+    /// input, whether or not it was delivered. Nothing already sent changes, this only
+    /// saves retyping it.
+    fn recall_submitted(&mut self) {
+        if let Some(text) = self.submitted.get(&self.current_channel.key()) {
+            self.input_text = text.clone();
+            self.set_status("Recalled your last message");
+        } else {
+            self.set_status(format!(
+                "Nothing was sent to {} yet",
+                self.current_channel.render()
+            ));
+        }
+    }
+
+    /// Puts an excerpt of the message with `id` in front of the chat draft.
+    fn quote(&mut self, id: u64) {
+        let excerpt = self.entries.iter().find_map(|entry| match entry {
+            Entry::Message(msg, _) if msg.id == id => {
+                let first_line = msg.text.lines().next().unwrap_or_default();
+                let mut excerpt: String = first_line.chars().take(QUOTE_LENGTH).collect();
+                if excerpt.len() < msg.text.len() {
+                    excerpt.push('…');
+                }
+                Some(excerpt)
+            }
+            _ => None,
+        });
+
+        if let Some(excerpt) = excerpt {
+            // The chat draft is the inactive one while the command is typed
+            let draft = if self.current_mode == InputMode::Chat {
+                &mut self.input_text
+            } else {
+                &mut self.other_draft
+            };
+            draft.insert_str(0, &format!("> {} ", excerpt));
+            self.set_status(format!("Quoting message {}", id));
+        } else {
+            self.set_status(format!("No message with id {}", id));
+        }
+    }
+
+    /// Tracks the incoming message rate and warns in the status bar while it is above
+    /// the flood threshold.
+    fn track_rate(&mut self) {
+        if self.flood_threshold == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        self.recent_messages.push_back(now);
+        while matches!(self.recent_messages.front(), Some(time) if now.duration_since(*time) > FLOOD_WINDOW)
+        {
+            self.recent_messages.pop_front();
+        }
+
+        if self.recent_messages.len() > self.flood_threshold {
+            self.set_status(format!(
+                "Flood warning: {} messages in the last second",
+                self.recent_messages.len()
+            ));
+        }
+    }
+
+    /// Counts `msg` as a repeat of the newest message if it is identical to it.
+    /// Returns whether it was, in which case it must not be pushed again.
+    fn coalesce(&mut self, msg: &ResolvedMessage) -> bool {
+        if !self.coalesce_repeats || msg.outgoing {
+            return false;
+        }
+
+        if let Some(Entry::Message(newest, _)) = self.entries.front_mut() {
+            if newest.outgoing
+                || newest.sender != msg.sender
+                || newest.channel.key() != msg.channel.key()
+                || newest.text != msg.text
+            {
+                return false;
+            }
+            newest.repeats += 1;
+        } else {
+            return false;
+        }
+
+        // The count is appended to the last line, so the entry keeps its line count
+        let lines = self.render_entry(&self.entries[0]);
+        for (slot, line) in self.lines.iter_mut().zip(lines) {
+            *slot = line;
+        }
+        true
+    }
+
+    /// Inserts a separator line when a message is from another day than the previous one.
+    fn separate_days(&mut self, date: NaiveDate) {
+        if matches!(self.last_date, Some(last) if last != date) {
+            self.last_message = None;
+            self.push_entry(Entry::Separator(date));
+        }
+        self.last_date = Some(date);
+    }
+
+    /// Scrolls towards older messages, which stops following new ones.
+    fn scroll_older(&mut self, lines: usize) {
+        if self.current_mode != InputMode::Scroll {
+            self.mode_before_scroll = self.current_mode;
+            self.current_mode = InputMode::Scroll;
+        }
+        self.scroll_y = (self.scroll_y + lines).min(self.lines.len().saturating_sub(1));
+    }
+
+    /// Number of `lines` that fit into the chat pane once wrapped, at least one so
+    /// that paging always moves.
+    fn page_lines<'b>(&self, lines: impl Iterator<Item = &'b Spans<'a>>) -> usize
+    where
+        'a: 'b,
+    {
+        let mut rows = 0;
+        lines
+            .take_while(|line| {
+                rows += util::wrapped_rows(line, self.chat_width);
+                rows <= self.chat_height
+            })
+            .count()
+            .max(1)
+    }
+
+    /// Scrolls one page of wrapped rows towards older messages.
+    fn page_older(&mut self) {
+        let lines = self.page_lines(self.lines.iter().skip(self.scroll_y));
+        self.scroll_older(lines);
+    }
+
+    /// Scrolls one page of wrapped rows towards newer messages.
+    fn page_newer(&mut self) {
+        let lines = self.page_lines(self.lines.range(..self.scroll_y).rev());
+        self.scroll_newer(lines);
+    }
+
+    fn scroll_newer(&mut self, lines: usize) {
+        self.scroll_y = self.scroll_y.saturating_sub(lines);
+        if self.scroll_y == 0 {
+            self.jump_to_newest();
+        }
+    }
+
+    /// Snaps back to the newest message and follows new ones again.
+    fn jump_to_newest(&mut self) {
+        self.scroll_y = 0;
+        self.selection = None;
+        if self.current_mode == InputMode::Scroll {
+            self.current_mode = self.mode_before_scroll;
+        }
+    }
+
+    fn push_lines(&mut self, lines: Vec<Spans<'a>>) {
+        let inserted = lines.len();
+        self.pushed_lines += inserted;
+        for line in lines.into_iter().rev() {
+            self.lines.push_front(line);
+        }
+        self.lines.truncate(self.scrollback);
+
+        if self.current_mode == InputMode::Scroll {
+            // Keep the view on the same lines now that newer ones were prepended
+            self.scroll_y = (self.scroll_y + inserted).min(self.lines.len().saturating_sub(1));
+            let last = self.lines.len().saturating_sub(1);
+            self.selection = self.selection.map(|(anchor, cursor)| {
+                ((anchor + inserted).min(last), (cursor + inserted).min(last))
+            });
+        } else {
+            self.scroll_y = 0;
+        }
+    }
+}
+
+/// A channel in the switcher, with its real name after an alias.
+fn switcher_label(channel: &ResolvedChannel) -> String {
+    if channel.alias.is_some() {
+        format!("{} ({})", channel.render(), channel.real_name())
+    } else {
+        channel.render()
+    }
+}
+
+async fn query_channels(
+    state_query_sender: &UnboundedSender<StateQuery>,
+) -> Option<Vec<ResolvedChannel>> {
+    let (tx, rx) = oneshot::channel();
+    state_query_sender.send(StateQuery::Channels(tx)).ok()?;
+    timeout(STATE_QUERY_TIMEOUT, rx).await.ok()?.ok()
+}
+
+async fn query_diagnostics(
+    state_query_sender: &UnboundedSender<StateQuery>,
+) -> Option<Diagnostics> {
+    let (tx, rx) = oneshot::channel();
+    state_query_sender.send(StateQuery::Diagnostics(tx)).ok()?;
+    timeout(STATE_QUERY_TIMEOUT, rx).await.ok()?.ok()
+}
+
+/// Asks the chat task for fresh diagnostics without waiting for the answer.
+fn request_diagnostics(
+    state_query_sender: &UnboundedSender<StateQuery>,
+) -> Option<oneshot::Receiver<Diagnostics>> {
+    let (tx, rx) = oneshot::channel();
+    state_query_sender.send(StateQuery::Diagnostics(tx)).ok()?;
+    Some(rx)
+}
+
+async fn query_invites(
+    state_query_sender: &UnboundedSender<StateQuery>,
+) -> Option<Vec<ResolvedChannel>> {
+    let (tx, rx) = oneshot::channel();
+    state_query_sender.send(StateQuery::Invites(tx)).ok()?;
+    timeout(STATE_QUERY_TIMEOUT, rx).await.ok()?.ok()
+}
+
+async fn toggle_diagnostics(app: &mut App<'_>, state_query_sender: &UnboundedSender<StateQuery>) {
+    if app.diagnostics.is_some() {
+        app.diagnostics = None;
+        return;
+    }
+
+    app.diagnostics = query_diagnostics(state_query_sender).await;
+    if app.diagnostics.is_none() {
+        app.set_status(String::from("Not connected"));
+    }
+}
+
+async fn toggle_channel_switcher(
+    app: &mut App<'_>,
+    state_query_sender: &UnboundedSender<StateQuery>,
+) {
+    if app.channel_switcher_open {
+        app.channel_switcher_open = false;
+        return;
+    }
+
+    if let Some(channels) = query_channels(state_query_sender).await {
+        app.channel_switcher_channels = channels;
+        app.channel_switcher_filter.clear();
+        app.channel_switcher_state.select(None);
+        app.channel_switcher_open = true;
+        app.palette_open = false;
+    } else {
+        app.set_status(String::from("Not connected"));
+    }
+}
+
+async fn list_invites(app: &mut App<'_>, state_query_sender: &UnboundedSender<StateQuery>) {
+    match query_invites(state_query_sender).await {
+        Some(invites) if invites.is_empty() => app.set_status("No pending invites"),
+        Some(invites) => {
+            let header = format!(
+                "{} pending invites, join one with /invites <number>:",
+                invites.len()
+            );
+            let text = std::iter::once(header)
+                .chain(
+                    invites
+                        .iter()
+                        .enumerate()
+                        .map(|(i, channel)| format!("{}. {}", i + 1, channel.render())),
+                )
+                .collect::<Vec<_>>()
+                .join("\n");
+            app.push_system(&text);
+        }
+        None => app.set_status("Not connected"),
+    }
+}
+
+/// Joins the private channel listed as `number` by `/invites`.
+async fn join_invite(
+    app: &mut App<'_>,
+    state_query_sender: &UnboundedSender<StateQuery>,
+    command_sender: &UnboundedSender<Command>,
+    number: usize,
+) {
+    match query_invites(state_query_sender).await {
+        Some(invites) => {
+            if let Some(channel) = number.checked_sub(1).and_then(|i| invites.get(i)) {
+                let _ = command_sender.send(Command::JoinId(channel.id));
+            } else {
+                app.set_status(format!("No invite number {}", number));
+            }
+        }
+        None => app.set_status("Not connected"),
+    }
+}
+
+async fn list_channels(app: &mut App<'_>, state_query_sender: &UnboundedSender<StateQuery>) {
+    if let Some(channels) = query_channels(state_query_sender).await {
+        let header = format!("{} known channels:", channels.len());
+        let text = std::iter::once(header)
+            .chain(channels.iter().enumerate().map(|(i, channel)| {
+                format!(
+                    "{}. {} ({})",
+                    i + 1,
+                    channel.render(),
+                    channel.r#type.describe()
+                )
+            }))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        app.push_system(&text);
+        app.channel_switcher_channels = channels;
+    } else {
+        app.set_status(String::from("Not connected"));
+    }
+}
+
+struct Args {
+    read_only: bool,
+    profile: Option<String>,
+    config: Option<PathBuf>,
+    /// Print events as JSON lines instead of running the TUI
+    json: bool,
+}
+
+fn parse_args() -> Args {
+    let mut args = Args {
+        read_only: false,
+        profile: None,
+        config: None,
+        json: false,
+    };
+    let mut raw = std::env::args().skip(1);
+
+    while let Some(arg) = raw.next() {
+        match arg.as_str() {
+            "--read-only" => args.read_only = true,
+            "--json" => args.json = true,
+            "--profile" => args.profile = raw.next(),
+            "--config" => args.config = raw.next().map(PathBuf::from),
+            _ => {}
+        }
+    }
+
+    args
+}
+
+fn pick_profile(mut profiles: Vec<Config>, wanted: Option<&str>) -> io::Result<Config> {
+    if let Some(name) = wanted {
+        return profiles
+            .into_iter()
+            .find(|p| p.profile == name)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No profile named {} in config file", name),
+                )
+            });
+    }
+
+    if profiles.len() == 1 {
+        return Ok(profiles.remove(0));
+    }
+
+    println!("Select a profile:");
+    for (i, profile) in profiles.iter().enumerate() {
+        println!(
+            "  {}) {} ({})",
+            i + 1,
+            profile.profile,
+            profile.character_name
+        );
+    }
+
+    loop {
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "No profile selected",
+            ));
+        }
+
+        match line.trim().parse::<usize>() {
+            Ok(n) if (1..=profiles.len()).contains(&n) => return Ok(profiles.remove(n - 1)),
+            _ => println!("Please enter a number between 1 and {}", profiles.len()),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let args = parse_args();
+    let read_only = args.read_only;
+
+    let config_path = if let Some(path) = args.config {
+        path
+    } else {
+        let project_dirs = ProjectDirs::from("org", "Nadybot", "ao-chat-client")
+            .expect("No valid home directory path provided by OS");
+        project_dirs.config_dir().join("config.txt")
+    };
 
     if !config_path.exists() {
-        write(&config_path, "USERNAME=\nPASSWORD=\nCHARNAME=\n")?;
+        if let Some(dir) = config_path.parent() {
+            create_dir_all(dir)?;
+        }
+        write(&config_path, config::TEMPLATE)?;
         println!(
             "No configuration file found, I created one at {:?}. Please fill it in.",
             config_path
@@ -74,246 +1049,620 @@ async fn main() -> io::Result<()> {
         std::process::exit(1);
     }
 
-    let config = config::load(&config_path)
-        .expect("Failed to read config file, please check formatting and permissions");
+    let profiles = match config::load(&config_path) {
+        Ok(profiles) => profiles,
+        Err(e) => {
+            eprintln!("{} ({})", e, config_path.display());
+            std::process::exit(1);
+        }
+    };
+    let config = pick_profile(profiles, args.profile.as_deref())?;
 
-    let (mut terminal, _cleanup) = term::init_crossterm()?;
-    terminal.clear()?;
+    let ignored = WordList::load(config_path.with_file_name("ignore.json"));
+    let highlights = WordList::load(config_path.with_file_name("highlights.json"));
+    let channel_aliases = ChannelAliases::load(config_path.with_file_name("channel_aliases.json"));
+
+    if args.json {
+        return json::run(config, read_only, ignored, highlights, channel_aliases).await;
+    }
+
+    let (mut terminal, cleanup) = term::init_crossterm(config.alt_screen)?;
 
     let mut input = input::EventStream::new();
 
-    let sock = AOSocket::connect("chat.d1.funcom.com:7105", SocketConfig::default())
-        .await
-        .unwrap();
-    let mut app = App {
-        current_mode: InputMode::Command,
-        channel_switcher_open: false,
-        channel_switcher_state: ListState::default(),
-        channel_switcher_channels: Vec::new(),
-        current_channel: ResolvedChannel {
-            id: 0,
-            name: String::from("Vicinity"),
-            r#type: ChannelType::Vicinity,
-        },
-        input_text: String::new(),
-        status_text: String::from("Initialized"),
-        messages: Text::raw(""),
-        scroll_y: 0,
-    };
+    let (highlighter, highlight_errors) = Highlighter::new(
+        &[config.highlights.as_slice(), highlights.words()].concat(),
+        &config.highlight_patterns,
+    );
+
+    let mut app = App::new(&config, read_only, highlighter);
+
+    for error in highlight_errors {
+        app.push_system(&error);
+    }
+    let conflicts: Vec<String> = app
+        .aliases
+        .keys()
+        .filter(|name| command::is_builtin(name))
+        .cloned()
+        .collect();
+    for name in conflicts {
+        app.aliases.remove(&name);
+        app.push_system(&format!(
+            "Alias /{} conflicts with a built-in command and is ignored",
+            name
+        ));
+    }
 
     let (state_query_sender, state_query_receiver) = unbounded_channel();
     let (command_sender, command_receiver) = unbounded_channel();
     let (ui_update_sender, mut ui_update_receiver) = unbounded_channel();
+    #[cfg(unix)]
+    if let Some(path) = config.control_socket.clone() {
+        tokio::spawn(control::control_task(
+            path,
+            command_sender.clone(),
+            ui_update_sender.clone(),
+            read_only,
+            config.prefixes.clone(),
+        ));
+    }
     tokio::spawn(chat::chat_task(
-        sock,
+        transport::AoConnector,
         state_query_receiver,
         command_receiver,
         ui_update_sender,
-        config.user_name.clone(),
-        config.character_name.clone(),
-        config.password.clone(),
+        config.clone(),
+        read_only,
+        ignored,
+        highlights,
+        channel_aliases,
     ));
 
-    loop {
-        terminal.draw(|f| {
-            // Split up into chat layer and two bars
-            let size = f.size();
-            let chunks = Layout::default()
-                .direction(Direction::Vertical)
-                .margin(0)
-                .constraints(
-                    [
-                        Constraint::Min(0),
-                        Constraint::Length(1),
-                        Constraint::Length(1),
-                    ]
-                    .as_ref(),
-                )
-                .split(size);
+    let mut clock_interval = interval(CLOCK_INTERVAL);
+    let mut frame_interval = interval(FRAME_INTERVAL);
+    let mut dirty = true;
+    let mut frame_due = true;
+    let mut last_interrupt: Option<Instant> = None;
+    let mut last_input = Instant::now();
+    // Printed after the terminal was restored
+    let mut exit_reason: Option<String> = None;
+    let mut failed = false;
+    // Refresh of the diagnostics overlay, answered while the UI keeps going
+    let mut diagnostics_refresh: Option<oneshot::Receiver<Diagnostics>> = None;
 
-            // Set background look
-            let block = Block::default().style(
-                Style::default()
-                    .bg(Color::Rgb(51, 51, 51))
-                    .fg(Color::LightYellow),
-            );
-            f.render_widget(block, size);
-
-            let chat_block = Paragraph::new(app.messages.clone())
-                .scroll((app.scroll_y as u16, 0))
-                .wrap(Wrap { trim: false })
-                .block(Block::default());
-            f.render_widget(chat_block, chunks[0]);
-
-            // Status bar
-            let status_bar = match app.current_mode {
-                InputMode::Command => {
-                    Paragraph::new(format!("[Mode: Command] {}", app.status_text))
-                        .block(Block::default().style(Style::default().bg(ORANGE).fg(Color::Black)))
-                        .alignment(Alignment::Left)
-                        .wrap(Wrap { trim: true })
-                }
-                InputMode::Scroll => Paragraph::new(format!("[Mode: Scroll] {}", app.status_text))
-                    .block(Block::default().style(Style::default().bg(Color::Red).fg(Color::White)))
-                    .alignment(Alignment::Left)
-                    .wrap(Wrap { trim: true }),
-                InputMode::Chat => Paragraph::new(format!("[Mode: Chat] {}", app.status_text))
-                    .block(
-                        Block::default().style(Style::default().bg(Color::Blue).fg(Color::White)),
+    loop {
+        // Bursts of updates are coalesced into at most one redraw per frame
+        if dirty && frame_due {
+            dirty = false;
+            frame_due = false;
+            terminal.draw(|f| {
+                // Split up into chat layer and two bars
+                let size = f.size();
+                let chunks = Layout::default()
+                    .direction(Direction::Vertical)
+                    .margin(0)
+                    .constraints(
+                        [
+                            Constraint::Min(0),
+                            Constraint::Length(1),
+                            Constraint::Length(1),
+                        ]
+                        .as_ref(),
                     )
-                    .alignment(Alignment::Left)
-                    .wrap(Wrap { trim: true }),
-            };
-            f.render_widget(status_bar, chunks[1]);
+                    .split(size);
 
-            let input_bar =
-                Block::default().style(Style::default().bg(Color::Black).fg(Color::White));
-            f.render_widget(input_bar, chunks[2]);
-
-            let input_paragraph = Paragraph::new(app.input_text.as_str());
+                // Set background look
+                let block = Block::default().style(
+                    Style::default()
+                        .bg(app.theme.background)
+                        .fg(app.theme.foreground),
+                );
+                f.render_widget(block, size);
 
-            if let InputMode::Chat = app.current_mode {
-                let channel_text = format!("[{}]", app.current_channel.render());
+                // The border takes a row and column on each side, the messages go inside it
+                let chat_area = if app.chat_border {
+                    let border = Block::default()
+                        .borders(Borders::ALL)
+                        .title(app.current_channel.render());
+                    let inner = border.inner(chunks[0]);
+                    f.render_widget(border, chunks[0]);
+                    inner
+                } else {
+                    chunks[0]
+                };
 
-                let input_bar_layout = Layout::default()
+                // Reserve the rightmost column for the scrollbar once messages overflow the pane
+                let scrollbar =
+                    util::scrollbar(app.lines.len(), app.scroll_y, chat_area.height.into());
+                let chat_layout = Layout::default()
                     .direction(Direction::Horizontal)
-                    .margin(0)
                     .constraints(
                         [
-                            Constraint::Length(channel_text.len() as u16),
-                            Constraint::Length(1),
                             Constraint::Min(0),
+                            Constraint::Length(u16::from(!scrollbar.is_empty())),
                         ]
                         .as_ref(),
                     )
-                    .split(chunks[2]);
+                    .split(chat_area);
 
-                let channel_indictator = Paragraph::new(channel_text);
+                app.chat_height = chat_area.height.into();
+                app.chat_width = chat_layout[0].width.into();
+                let chat_block = Paragraph::new(app.visible_lines())
+                    .wrap(Wrap { trim: false })
+                    .block(Block::default());
+                f.render_widget(chat_block, chat_layout[0]);
+                f.render_widget(Paragraph::new(scrollbar), chat_layout[1]);
 
-                f.render_widget(channel_indictator, input_bar_layout[0]);
-                f.render_widget(input_paragraph, input_bar_layout[2]);
+                // Status bar
+                let read_only_tag = if app.read_only { " [READ-ONLY]" } else { "" };
+                let bar_style = match app.current_mode {
+                    InputMode::Command => Style::default()
+                        .bg(app.theme.command_bar)
+                        .fg(app.theme.command_bar_text),
+                    InputMode::Scroll => Style::default()
+                        .bg(app.theme.scroll_bar)
+                        .fg(app.theme.scroll_bar_text),
+                    InputMode::Chat => Style::default()
+                        .bg(app.theme.chat_bar)
+                        .fg(app.theme.chat_bar_text),
+                };
+                // The minimal bar only colors the mode indicator
+                let status_bar = if app.minimal_statusbar {
+                    Paragraph::new(Spans::from(vec![
+                        Span::styled(format!(" {} ", app.current_mode.indicator()), bar_style),
+                        Span::raw(format!("{} {}", read_only_tag, app.status())),
+                    ]))
+                } else {
+                    Paragraph::new(format!(
+                        "[Mode: {}]{} {}",
+                        app.current_mode.name(),
+                        read_only_tag,
+                        app.status()
+                    ))
+                    .block(Block::default().style(bar_style))
+                };
+                let status_bar = status_bar
+                    .alignment(Alignment::Left)
+                    .wrap(Wrap { trim: true });
+                f.render_widget(status_bar, chunks[1]);
 
-                f.set_cursor(
-                    input_bar_layout[2].x + app.input_text.len() as u16,
-                    input_bar_layout[2].y,
+                let latency = app
+                    .latency
+                    .map_or_else(|| String::from("-"), |l| format!("{}ms", l.as_millis()));
+                // New messages only keep the view pinned outside of Scroll mode
+                let follow = if app.current_mode == InputMode::Scroll {
+                    "⏸"
+                } else {
+                    "▶"
+                };
+                let connection_color = match app.connection {
+                    ConnectionState::Ready => Color::Green,
+                    ConnectionState::Disconnected => Color::Red,
+                    ConnectionState::Connecting
+                    | ConnectionState::Authenticating
+                    | ConnectionState::Reconnecting => Color::Yellow,
+                };
+                let clock = Paragraph::new(Spans::from(vec![
+                    Span::styled("● ", Style::default().fg(connection_color)),
+                    Span::raw(format!("{} ", app.character_name)),
+                    Span::raw(format!(
+                        "{} ⟳ {} {} ",
+                        follow,
+                        latency,
+                        Local::now().format("%H:%M:%S")
+                    )),
+                ]))
+                .alignment(Alignment::Right);
+                f.render_widget(clock, chunks[1]);
+
+                let input_bar = Block::default().style(
+                    Style::default()
+                        .bg(app.theme.input_bar)
+                        .fg(app.theme.input_bar_text),
                 );
-            } else {
-                f.render_widget(input_paragraph, chunks[2]);
+                f.render_widget(input_bar, chunks[2]);
 
-                f.set_cursor(chunks[2].x + app.input_text.len() as u16, chunks[2].y);
-            }
+                let prompt_layout = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([Constraint::Length(2), Constraint::Min(0)].as_ref())
+                    .split(chunks[2]);
+                let prompt_color = match app.current_mode {
+                    InputMode::Command => app.theme.command_bar,
+                    InputMode::Chat => app.theme.chat_bar,
+                    InputMode::Scroll => app.theme.scroll_bar,
+                };
+                let prompt = Paragraph::new(Span::styled(
+                    app.current_mode.prompt(),
+                    Style::default()
+                        .fg(prompt_color)
+                        .add_modifier(Modifier::BOLD),
+                ));
+                f.render_widget(prompt, prompt_layout[0]);
+
+                let input_paragraph = Paragraph::new(app.input_text.as_str());
+
+                if let InputMode::Chat = app.current_mode {
+                    // Long channel names must leave room for the input
+                    let channel_text = util::truncate_to_width(
+                        &format!("[{}]", app.current_channel.render()),
+                        usize::from(prompt_layout[1].width / 2),
+                    );
+                    let bytes = app.decoration.apply(&app.input_text).len();
+                    let counter_text = format!(" {}/{}", bytes, MESSAGE_BYTE_LIMIT);
+
+                    let input_bar_layout = Layout::default()
+                        .direction(Direction::Horizontal)
+                        .margin(0)
+                        .constraints(
+                            [
+                                Constraint::Length(util::cells(channel_text.width())),
+                                Constraint::Length(1),
+                                Constraint::Min(0),
+                                Constraint::Length(util::cells(counter_text.width())),
+                            ]
+                            .as_ref(),
+                        )
+                        .split(prompt_layout[1]);
+
+                    let channel_indictator = Paragraph::new(channel_text);
+                    // Warn once the message gets within 10% of the limit
+                    let counter_style = if bytes * 10 >= MESSAGE_BYTE_LIMIT * 9 {
+                        Style::default().fg(app.theme.warning)
+                    } else {
+                        Style::default()
+                    };
+                    let counter = Paragraph::new(Span::styled(counter_text, counter_style));
 
-            if app.channel_switcher_open {
-                if !app.channel_switcher_channels.is_empty()
-                    && app.channel_switcher_state.selected().is_none()
-                {
-                    app.channel_switcher_state.select(Some(0));
+                    f.render_widget(channel_indictator, input_bar_layout[0]);
+                    f.render_widget(input_paragraph, input_bar_layout[2]);
+                    f.render_widget(counter, input_bar_layout[3]);
+
+                    let (x, y) = util::cursor_after(&app.input_text, input_bar_layout[2]);
+                    f.set_cursor(x, y);
+                } else {
+                    f.render_widget(input_paragraph, prompt_layout[1]);
+
+                    let (x, y) = util::cursor_after(&app.input_text, prompt_layout[1]);
+                    f.set_cursor(x, y);
                 }
 
-                let popup = List::new(
-                    app.channel_switcher_channels
-                        .iter()
-                        .map(|c| ListItem::new(c.render()))
-                        .collect::<Vec<ListItem>>(),
-                )
-                .block(
-                    Block::default()
-                        .title("Channel switcher")
-                        .borders(Borders::ALL),
-                )
-                .highlight_style(Style::default().add_modifier(Modifier::ITALIC))
-                .highlight_symbol(">>");
-                let area = util::centered_rect(60, 50, size);
-                f.render_widget(Clear, area);
-                f.render_stateful_widget(popup, area, &mut app.channel_switcher_state);
-            }
-        })?;
+                if app.channel_switcher_open {
+                    // The channel list can change while the switcher is open
+                    let len = app.switcher_matches().len();
+                    util::fit_selection(&mut app.channel_switcher_state, len);
+
+                    let area = util::centered_rect(
+                        app.popup_width,
+                        app.popup_height,
+                        POPUP_MIN_WIDTH,
+                        POPUP_MIN_HEIGHT,
+                        size,
+                    );
+                    // Leave room for the borders and the highlight symbol
+                    let item_width = area
+                        .width
+                        .saturating_sub(2 + util::cells(SWITCHER_HIGHLIGHT.width()));
+
+                    let match_style =
+                        Style::default().add_modifier(Modifier::BOLD | Modifier::UNDERLINED);
+                    let items = app
+                        .switcher_matches()
+                        .into_iter()
+                        .map(|(c, indices)| {
+                            let mut label = switcher_label(c);
+                            if app.muted.contains(&c.key()) {
+                                label.push_str(" (muted)");
+                            }
+                            let name = util::truncate_to_width(&label, item_width.into());
+                            ListItem::new(util::highlight_chars(&name, &indices, match_style))
+                        })
+                        .collect::<Vec<ListItem>>();
+                    let title = if app.channel_switcher_filter.is_empty() {
+                        String::from("Channel switcher")
+                    } else {
+                        format!("Channel switcher: {}", app.channel_switcher_filter)
+                    };
+                    let title = util::list_title(title, &app.channel_switcher_state, len, area);
+
+                    let popup = List::new(items)
+                        .block(Block::default().title(title).borders(Borders::ALL))
+                        .highlight_style(
+                            Style::default()
+                                .fg(app.theme.highlight)
+                                .add_modifier(Modifier::ITALIC),
+                        )
+                        .highlight_symbol(SWITCHER_HIGHLIGHT);
+                    f.render_widget(Clear, area);
+                    f.render_stateful_widget(popup, area, &mut app.channel_switcher_state);
+                }
+
+                if app.palette_open {
+                    let len = app.palette_matches().len();
+                    util::fit_selection(&mut app.palette_state, len);
+
+                    let area = util::centered_rect(
+                        app.popup_width,
+                        app.popup_height,
+                        POPUP_MIN_WIDTH,
+                        POPUP_MIN_HEIGHT,
+                        size,
+                    );
+                    let item_width = area
+                        .width
+                        .saturating_sub(2 + util::cells(SWITCHER_HIGHLIGHT.width()));
+
+                    let match_style =
+                        Style::default().add_modifier(Modifier::BOLD | Modifier::UNDERLINED);
+                    let items = app
+                        .palette_matches()
+                        .into_iter()
+                        .map(|(c, indices)| {
+                            // Only the name is matched, so its indices stay valid after the slash
+                            let indices: Vec<usize> = indices.iter().map(|i| i + 1).collect();
+                            let label = format!("/{}  {}", c.name, c.description);
+                            let label = util::truncate_to_width(&label, item_width.into());
+                            ListItem::new(util::highlight_chars(&label, &indices, match_style))
+                        })
+                        .collect::<Vec<ListItem>>();
+                    let title = if app.palette_filter.is_empty() {
+                        String::from("Commands")
+                    } else {
+                        format!("Commands: {}", app.palette_filter)
+                    };
+                    let title = util::list_title(title, &app.palette_state, len, area);
+
+                    let popup = List::new(items)
+                        .block(Block::default().title(title).borders(Borders::ALL))
+                        .highlight_style(
+                            Style::default()
+                                .fg(app.theme.highlight)
+                                .add_modifier(Modifier::ITALIC),
+                        )
+                        .highlight_symbol(SWITCHER_HIGHLIGHT);
+                    f.render_widget(Clear, area);
+                    f.render_stateful_widget(popup, area, &mut app.palette_state);
+                }
+
+                if let Some(diagnostics) = &app.diagnostics {
+                    let area = util::centered_rect(
+                        app.popup_width,
+                        app.popup_height,
+                        POPUP_MIN_WIDTH,
+                        POPUP_MIN_HEIGHT,
+                        size,
+                    );
+                    let last_packet = diagnostics.last_packet.map_or_else(
+                        || String::from("none"),
+                        |time| {
+                            let ago = Local::now().signed_duration_since(time).num_seconds();
+                            format!("{} ({}s ago)", time.format("%H:%M:%S"), ago)
+                        },
+                    );
+                    let latency = app
+                        .latency
+                        .map_or_else(|| String::from("-"), |l| format!("{}ms", l.as_millis()));
+                    let mut lines = vec![
+                        format!("Connection: {}", app.connection.describe()),
+                        format!("Latency: {}", latency),
+                        format!("Last packet: {}", last_packet),
+                        format!("Reconnects: {}", diagnostics.reconnects),
+                        format!("Queued in the outbox: {}", diagnostics.queued),
+                        String::from("Packets received:"),
+                    ];
+                    lines.extend(
+                        diagnostics
+                            .packet_counts
+                            .iter()
+                            .map(|(name, count)| format!("  {}: {}", name, count)),
+                    );
+
+                    let popup = Paragraph::new(lines.join("\n"))
+                        .block(Block::default().title("Diagnostics").borders(Borders::ALL));
+                    f.render_widget(Clear, area);
+                    f.render_widget(popup, area);
+                }
+            })?;
+        }
 
         tokio::select! {
+            _ = frame_interval.tick() => frame_due = true,
+
             input = input.next() => {
+                dirty = true;
+                last_input = Instant::now();
                 if let Some(maybe_event) = input {
                     let event = maybe_event?;
-                    if input::should_quit(&event) {
-                        break;
+                    match input::quit_key(&event, app.current_mode == InputMode::Scroll) {
+                        Some(input::QuitKey::Interrupt) => {
+                            if matches!(last_interrupt, Some(last) if last.elapsed() < QUIT_CONFIRM_WINDOW) {
+                                break;
+                            }
+                            last_interrupt = Some(Instant::now());
+                            app.set_status(String::from("Press Ctrl+C again to quit"));
+                            continue;
+                        }
+                        Some(input::QuitKey::EndOfFile) => {
+                            // Like EOF on a terminal, only quit when there is nothing to lose
+                            if app.input_text.trim_start_matches('/').is_empty() {
+                                break;
+                            }
+                            continue;
+                        }
+                        None => {}
                     }
 
                     if let input::Event::Key(key) = event {
                         match key {
+                            input::KeyEvent { code: input::KeyCode::Backspace, .. } if app.channel_switcher_open => {
+                                app.channel_switcher_filter.pop();
+                                app.channel_switcher_state.select(None);
+                            },
+                            input::KeyEvent { code: input::KeyCode::Backspace, .. } if app.palette_open => {
+                                app.palette_filter.pop();
+                                app.palette_state.select(None);
+                            },
                             input::KeyEvent { code: input::KeyCode::Backspace, .. } => {
                                 app.input_text.pop();
                             },
-                            input::KeyEvent { code: input::KeyCode::Up, ..} if app.channel_switcher_open => {
-                                let i = match app.channel_switcher_state.selected() {
-                                    Some(i) => {
-                                        if i == 0 {
-                                            app.channel_switcher_channels.len() - 1
-                                        } else {
-                                            i - 1
-                                        }
-                                    }
-                                    None => 0,
-                                };
-                                app.channel_switcher_state.select(Some(i));
+                            input::KeyEvent { code: code @ (input::KeyCode::Up | input::KeyCode::Down), ..} if app.channel_switcher_open => {
+                                let len = app.switcher_matches().len();
+                                util::step_selection(&mut app.channel_switcher_state, len, code == input::KeyCode::Up);
                             }
-                            input::KeyEvent { code: input::KeyCode::Down, ..} if app.channel_switcher_open => {
-                                let i = match app.channel_switcher_state.selected() {
-                                    Some(i) => {
-                                        if i >= app.channel_switcher_channels.len() - 1 {
-                                            0
-                                        } else {
-                                            i + 1
-                                        }
-                                    }
-                                    None => 0,
-                                };
-                                app.channel_switcher_state.select(Some(i));
+                            input::KeyEvent { code: code @ (input::KeyCode::Up | input::KeyCode::Down), ..} if app.palette_open => {
+                                let len = app.palette_matches().len();
+                                util::step_selection(&mut app.palette_state, len, code == input::KeyCode::Up);
                             }
-                            input::KeyEvent { code: input::KeyCode::Enter, .. } => {
-                                if app.channel_switcher_open {
-                                    app.current_channel = app.channel_switcher_channels[app.channel_switcher_state.selected().unwrap()].clone();
+                            input::KeyEvent { code: input::KeyCode::Enter, .. } if app.palette_open => {
+                                let selected = app.palette_state.selected().unwrap_or(0);
+                                let command = app.palette_matches().get(selected).map(|(c, _)| *c);
+                                if let Some(command) = command {
+                                    app.pick_command(command);
+                                }
+                            }
+                            input::KeyEvent { code: input::KeyCode::Esc, .. } if app.palette_open => app.palette_open = false,
+                            input::KeyEvent { code: input::KeyCode::Enter, .. } if app.channel_switcher_open => {
+                                let selected = app.channel_switcher_state.selected().unwrap_or(0);
+                                let channel = app.switcher_matches().get(selected).map(|(c, _)| (*c).clone());
+                                if let Some(channel) = channel {
+                                    app.current_channel = channel;
                                     app.channel_switcher_open = false;
                                     app.current_mode = InputMode::Chat;
-                                } else if InputMode::Chat == app.current_mode {
-                                    let text = app.input_text.clone();
-                                    app.input_text.clear();
+                                }
+                            }
+                            input::KeyEvent { code: input::KeyCode::Enter, .. } => {
+                                if InputMode::Chat == app.current_mode {
+                                    if app.can_send() {
+                                        let text = app.input_text.clone();
+                                        app.input_text.clear();
+                                        app.submitted.insert(app.current_channel.key(), text.clone());
 
-                                    let _ = command_sender.send(Command::Message(app.current_channel.clone(), text));
+                                        let _ = command_sender.send(Command::Message(app.current_channel.clone(), text));
+                                    }
                                 } else if InputMode::Command == app.current_mode {
-                                    let command = command::Command::from_input(&app.input_text);
+                                    let input = command::expand_alias(&app.input_text, &app.aliases)
+                                        .unwrap_or_else(|| app.input_text.clone());
+                                    let command = command::Command::from_input(&input);
                                     app.input_text.clear();
 
                                     if let Some(cmd) = command {
-                                        let cmd = cmd.into();
-                                        let _ = command_sender.send(cmd);
+                                        if app.read_only && !cmd.is_read_only() {
+                                            app.set_status(String::from("Read-only mode, nothing was sent"));
+                                            continue;
+                                        }
+                                        if app.connection != ConnectionState::Ready && !cmd.is_read_only() && !cmd.is_queued() {
+                                            app.set_status(String::from("Not connected, nothing was sent"));
+                                            continue;
+                                        }
+                                        match cmd {
+                                            command::Command::Channels => list_channels(&mut app, &state_query_sender).await,
+                                            command::Command::Invites => list_invites(&mut app, &state_query_sender).await,
+                                            command::Command::JoinInvite(number) => {
+                                                join_invite(&mut app, &state_query_sender, &command_sender, number).await;
+                                            }
+                                            command::Command::Export(path) => app.export(&path),
+                                            command::Command::Toggle(toggle) => app.toggle(&toggle),
+                                            command::Command::Topic(text) => app.topic(text),
+                                            command::Command::Quote(id) => app.quote(id),
+                                            command::Command::Help => app.push_system(&command::help()),
+                                            cmd => if let Some(cmd) = Command::new(cmd, &app.current_channel) {
+                                                let _ = command_sender.send(cmd);
+                                            },
+                                        }
+                                        if app.return_to_chat {
+                                            // Brings back the chat draft, like Esc does
+                                            std::mem::swap(&mut app.input_text, &mut app.other_draft);
+                                            app.current_mode = InputMode::Chat;
+                                        }
                                     } else {
-                                        app.status_text = String::from("Error in command syntax");
+                                        app.set_status(String::from("Error in command syntax"));
                                     }
                                 }
                             }
+                            input::KeyEvent { code: input::KeyCode::Up, .. } if app.current_mode == InputMode::Chat && app.input_text.is_empty() => app.recall_submitted(),
+                            input::KeyEvent { code: input::KeyCode::PageUp, .. } => app.page_older(),
+                            input::KeyEvent { code: input::KeyCode::PageDown, .. } => app.page_newer(),
+                            input::KeyEvent { code: input::KeyCode::End, .. } => app.jump_to_newest(),
+                            input::KeyEvent { code: input::KeyCode::Up, modifiers } if app.current_mode == InputMode::Scroll && modifiers.contains(input::KeyModifiers::SHIFT) => {
+                                app.extend_selection(false);
+                            },
+                            input::KeyEvent { code: input::KeyCode::Down, modifiers } if app.current_mode == InputMode::Scroll && modifiers.contains(input::KeyModifiers::SHIFT) => {
+                                app.extend_selection(true);
+                            },
+                            input::KeyEvent { code: input::KeyCode::Char('y'), modifiers } if app.selection.is_some() && !modifiers.contains(input::KeyModifiers::CONTROL) => app.copy_selection(),
+                            input::KeyEvent { code: input::KeyCode::Char('l'), modifiers } if app.current_mode == InputMode::Scroll && !modifiers.contains(input::KeyModifiers::CONTROL) => app.show_links(),
+                            input::KeyEvent { code: input::KeyCode::Esc, .. } if app.selection.is_some() => app.selection = None,
+                            input::KeyEvent { code: input::KeyCode::Char('u'), modifiers } if app.current_mode == InputMode::Scroll && modifiers.contains(input::KeyModifiers::CONTROL) => {
+                                app.scroll_older((app.chat_height / 2).max(1));
+                            },
+                            input::KeyEvent { code: input::KeyCode::Char('d'), modifiers } if app.current_mode == InputMode::Scroll && modifiers.contains(input::KeyModifiers::CONTROL) => {
+                                app.scroll_newer((app.chat_height / 2).max(1));
+                            },
+                            input::KeyEvent { code: input::KeyCode::Up | input::KeyCode::Char('k'), modifiers } if app.current_mode == InputMode::Scroll && !modifiers.contains(input::KeyModifiers::CONTROL) => app.scroll_older(1),
+                            input::KeyEvent { code: input::KeyCode::Down | input::KeyCode::Char('j'), modifiers } if app.current_mode == InputMode::Scroll && !modifiers.contains(input::KeyModifiers::CONTROL) => app.scroll_newer(1),
+                            input::KeyEvent { code: input::KeyCode::Char('g'), modifiers } if app.current_mode == InputMode::Scroll && !modifiers.contains(input::KeyModifiers::CONTROL) => app.scroll_older(app.lines.len()),
+                            input::KeyEvent { code: input::KeyCode::Char('G') | input::KeyCode::Esc, .. } if app.current_mode == InputMode::Scroll => {
+                                app.jump_to_newest();
+                            },
                             input::KeyEvent { code: input::KeyCode::Esc, .. } => {
-                                app.input_text.clear();
+                                std::mem::swap(&mut app.input_text, &mut app.other_draft);
                                 if InputMode::Command == app.current_mode {
                                     app.current_mode = InputMode::Chat;
                                 } else {
                                     app.current_mode = InputMode::Command;
+                                    if app.input_text.is_empty() {
+                                        app.input_text.push('/');
+                                    }
+                                }
+                            },
+                            input::KeyEvent { code: input::KeyCode::Char('u'), modifiers } if modifiers.contains(input::KeyModifiers::CONTROL) => {
+                                app.input_text.clear();
+                                if InputMode::Command == app.current_mode {
                                     app.input_text.push('/');
                                 }
                             },
+                            input::KeyEvent { code: input::KeyCode::Char('y'), modifiers } if app.current_mode == InputMode::Chat && modifiers.contains(input::KeyModifiers::CONTROL) => {
+                                // Resends the last message to the current channel, which may differ from where it went
+                                if let Some(text) = app.last_sent.clone() {
+                                    if app.can_send() {
+                                        let _ = command_sender.send(Command::Message(app.current_channel.clone(), text));
+                                    }
+                                } else {
+                                    app.set_status("Nothing was sent yet");
+                                }
+                            },
+                            input::KeyEvent { code: input::KeyCode::F(2), .. } => {
+                                app.compact = !app.compact;
+                                app.set_status(format!("Compact mode {}", if app.compact { "on" } else { "off" }));
+                            },
+                            input::KeyEvent { code: input::KeyCode::F(3), .. } => {
+                                toggle_diagnostics(&mut app, &state_query_sender).await;
+                            },
                             input::KeyEvent { code: input::KeyCode::Tab, .. } => {
-                                app.channel_switcher_open = !app.channel_switcher_open;
-                                let (tx, rx) = oneshot::channel();
-                                let query = StateQuery::Channels(tx);
-                                let _ = state_query_sender.send(query);
-                                let channels = rx.await.unwrap();
-                                app.channel_switcher_channels = channels;
+                                toggle_channel_switcher(&mut app, &state_query_sender).await;
                             },
                             input::KeyEvent { code: input::KeyCode::Char('k'), modifiers } if modifiers.contains(input::KeyModifiers::CONTROL) => {
-                                app.channel_switcher_open = !app.channel_switcher_open;
-                                let (tx, rx) = oneshot::channel();
-                                let query = StateQuery::Channels(tx);
-                                let _ = state_query_sender.send(query);
-                                let channels = rx.await.unwrap();
-                                app.channel_switcher_channels = channels;
+                                toggle_channel_switcher(&mut app, &state_query_sender).await;
+                            },
+                            input::KeyEvent { code: input::KeyCode::Char('p'), modifiers } if modifiers.contains(input::KeyModifiers::CONTROL) => {
+                                app.toggle_palette();
+                            },
+                            input::KeyEvent { code: input::KeyCode::Char(c @ '1'..='9'), modifiers } if modifiers.contains(input::KeyModifiers::ALT) => {
+                                let index = c as usize - '1' as usize;
+                                if let Some(channel) = app.channel_switcher_channels.get(index) {
+                                    app.current_channel = channel.clone();
+                                    app.current_mode = InputMode::Chat;
+                                } else {
+                                    app.set_status(format!("No channel at position {}", c));
+                                }
+                            },
+                            input::KeyEvent { code: input::KeyCode::Char(c), modifiers } if app.channel_switcher_open && !modifiers.intersects(input::KeyModifiers::CONTROL | input::KeyModifiers::ALT) => {
+                                app.channel_switcher_filter.push(c);
+                                app.channel_switcher_state.select(None);
+                            },
+                            input::KeyEvent { code: input::KeyCode::Char(c), modifiers } if app.palette_open && !modifiers.intersects(input::KeyModifiers::CONTROL | input::KeyModifiers::ALT) => {
+                                app.palette_filter.push(c);
+                                app.palette_state.select(None);
                             },
                             input::KeyEvent { code: input::KeyCode::Char(c), .. } => app.input_text.push(c),
                             _ => {},
@@ -324,23 +1673,168 @@ async fn main() -> io::Result<()> {
                 }
             },
 
+            _ = clock_interval.tick() => {
+                dirty = true;
+                // Leave nothing logged in on an unattended machine
+                if matches!(config.idle_logout, Some(idle) if last_input.elapsed() >= idle) {
+                    exit_reason = Some(String::from("Logged out after being idle"));
+                    break;
+                }
+                if app.diagnostics.is_some() && diagnostics_refresh.is_none() {
+                    diagnostics_refresh = request_diagnostics(&state_query_sender);
+                }
+            },
+
+            diagnostics = async { diagnostics_refresh.as_mut().unwrap().await }, if diagnostics_refresh.is_some() => {
+                diagnostics_refresh = None;
+                // A busy or reconnecting chat task keeps the last snapshot on screen
+                if let (Ok(diagnostics), true) = (diagnostics, app.diagnostics.is_some()) {
+                    app.diagnostics = Some(diagnostics);
+                    dirty = true;
+                }
+            },
+
             ui_update = ui_update_receiver.recv() => {
+                dirty = true;
                 if let Some(update) = ui_update {
                     match update {
-                        UiUpdate::Message(msg) => {
-                            let rendered = msg.render();
-                            app.messages.lines.splice(0..0, rendered);
+                        UiUpdate::Fatal(reason) => {
+                            exit_reason = Some(reason);
+                            failed = true;
+                            break;
+                        },
+                        UiUpdate::Message(mut msg) => {
+                            if !msg.outgoing
+                                && ((app.bell_on.tell && msg.channel.r#type == ChannelType::Tell)
+                                    || (app.bell_on.highlight && app.highlighter.is_match(&msg.text)))
+                            {
+                                app.ring_bell();
+                            }
+
+                            if !msg.outgoing {
+                                app.track_rate();
+                            }
+                            // Never retarget a message that is being typed
+                            if app.focus_follow && !msg.outgoing && app.input_text.is_empty() {
+                                app.current_channel = msg.channel.clone();
+                            }
+                            app.separate_days(app.render_options.date(msg.timestamp));
+                            if app.coalesce(&msg) {
+                                continue;
+                            }
 
-                            if app.current_mode != InputMode::Scroll {
-                                app.scroll_y = 0;
+                            // In compact mode, repeated sender/channel prefixes are left out
+                            let key = (msg.sender.clone(), msg.channel.render());
+                            let continuation = app.compact && app.last_message.as_ref() == Some(&key);
+                            app.last_message = Some(key);
+                            let echo_id = msg.delivery.map(|(id, _)| id);
+                            msg.id = app.next_message_id;
+                            app.next_message_id += 1;
+                            app.push_entry(Entry::Message(msg, continuation));
+                            if let Some(id) = echo_id {
+                                app.pending_echoes.insert(id, app.pushed_lines);
+                            }
+                        },
+                        UiUpdate::System(text) => app.push_system(&text),
+                        UiUpdate::Status(text) => app.set_status(text),
+                        UiUpdate::Kick(user, channel) => {
+                            app.push_system(&format!("Kicked {} from {}", user, channel.render()));
+                        },
+                        UiUpdate::Leave(channel) => app.push_system(&format!("You left {}", channel.render())),
+                        UiUpdate::Kicked(channel) => {
+                            if app.current_channel.key() == channel.key() {
+                                app.current_channel = ResolvedChannel::vicinity(&app.prefixes);
                             }
+                            app.push_system(&format!("You were kicked from {}", channel.render()));
                         },
-                        _ => {},
+                        UiUpdate::Closed(channel) => {
+                            if app.current_channel.key() == channel.key() {
+                                app.current_channel = ResolvedChannel::vicinity(&app.prefixes);
+                            }
+                            app.set_status(format!("Closed {}", channel.render()));
+                        },
+                        UiUpdate::Latency(latency) => app.latency = Some(latency),
+                        UiUpdate::Channels(channels) => {
+                            // Picks up a changed alias of the current channel
+                            if let Some(channel) = channels.iter().find(|c| c.key() == app.current_channel.key()) {
+                                app.current_channel = channel.clone();
+                            }
+                            app.channel_switcher_channels = channels;
+                        },
+                        UiUpdate::Delivery(id, delivery) => app.update_delivery(id, delivery),
+                        UiUpdate::Connection(state) => app.connection = state,
+                        UiUpdate::Reconnected => {
+                            app.last_message = None;
+                            app.push_entry(Entry::Reconnected(Local::now()));
+                        },
+                        UiUpdate::Muted(channels) => app.muted = channels.into_iter().collect(),
+                        UiUpdate::TellSent(channel) => {
+                            if app.focus_tells {
+                                app.current_channel = channel;
+                            }
+                        },
+                        UiUpdate::Highlights(words) => {
+                            // Invalid patterns were already reported at startup
+                            app.highlighter = Highlighter::new(
+                                &[app.config_highlights.as_slice(), words.as_slice()].concat(),
+                                &app.highlight_patterns,
+                            )
+                            .0;
+                        },
+                        UiUpdate::Invite(_) => {},
                     }
                 }
             },
         };
     }
 
+    // Restore the terminal first, so the reason stays visible after exiting
+    drop(cleanup);
+    if let Some(reason) = exit_reason {
+        eprintln!("{}", reason);
+    }
+    if failed {
+        std::process::exit(1);
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGES: usize = 2000;
+
+    fn line_text(line: &Spans) -> String {
+        line.0.iter().map(|span| span.content.as_ref()).collect()
+    }
+
+    /// Only the lines that fit in the chat pane are built for a frame, starting at the
+    /// scroll position, however long the scrollback is.
+    #[test]
+    fn long_scrollback_renders_only_the_visible_window() {
+        let config = config::parse("USERNAME=user\nPASSWORD=secret\nCHARNAME=Tester\n")
+            .unwrap()
+            .remove(0);
+        let (highlighter, _) = Highlighter::new(&[], &[]);
+        let mut app = App::new(&config, false, highlighter);
+        app.chat_height = 50;
+        for i in 0..MESSAGES {
+            app.push_system(&format!("message {}", i));
+        }
+
+        let frame = app.visible_lines();
+        assert_eq!(frame.len(), app.chat_height);
+        for (i, line) in frame.iter().enumerate() {
+            assert!(line_text(line).ends_with(&format!("message {}", MESSAGES - 1 - i)));
+        }
+
+        app.scroll_y = 100;
+        let frame = app.visible_lines();
+        assert_eq!(frame.len(), app.chat_height);
+        for (i, line) in frame.iter().enumerate() {
+            assert!(line_text(line).ends_with(&format!("message {}", MESSAGES - 101 - i)));
+        }
+    }
+}
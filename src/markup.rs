@@ -0,0 +1,199 @@
+use std::ops::Range;
+
+/// A link from AO's chat markup, such as an item or a text blob.
+#[derive(Clone)]
+pub struct Link {
+    /// Byte range of the label in the plain text
+    pub range: Range<usize>,
+    /// The `href`, like `itemref://low/high/ql` or `text://...`
+    pub target: String,
+}
+
+impl Link {
+    /// The target in readable form. Text blobs carry markup of their own, which is stripped.
+    pub fn describe(&self) -> String {
+        match self.target.strip_prefix("text://") {
+            Some(blob) => parse(blob).0,
+            None => self.target.clone(),
+        }
+    }
+}
+
+/// Strips the HTML-like markup AO uses in chat messages, keeping the labels of links
+/// and turning `<br>` into line breaks. Returns the plain text and the links in it.
+/// Anything that doesn't look like a tag is kept as it is, except for control
+/// characters, which could otherwise move the cursor or recolor the terminal.
+pub fn parse(text: &str) -> (String, Vec<Link>) {
+    let mut plain = String::with_capacity(text.len());
+    let mut links = Vec::new();
+    let mut open_link: Option<(usize, String)> = None;
+    let mut rest = text;
+
+    while let Some(start) = rest.find('<') {
+        push_text(&mut plain, &rest[..start]);
+        rest = &rest[start..];
+
+        let tag = if let Some(end) = tag_end(rest) {
+            &rest[1..end]
+        } else {
+            plain.push('<');
+            rest = &rest[1..];
+            continue;
+        };
+        rest = &rest[tag.len() + 2..];
+
+        let name = tag
+            .split(|c: char| c.is_ascii_whitespace() || c == '/')
+            .find(|part| !part.is_empty())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if tag.starts_with('/') {
+            if name == "a" {
+                if let Some((start, target)) = open_link.take() {
+                    if start < plain.len() {
+                        links.push(Link {
+                            range: start..plain.len(),
+                            target,
+                        });
+                    }
+                }
+            }
+        } else if name == "a" {
+            open_link = href(tag).map(|target| (plain.len(), target));
+        } else if name == "br" {
+            plain.push('\n');
+        }
+    }
+    push_text(&mut plain, rest);
+
+    (plain, links)
+}
+
+/// Returns the index of the `>` closing the tag at the start of `text`, skipping quoted
+/// attribute values, which can contain markup themselves. Returns `None` if `text`
+/// doesn't start with a tag.
+fn tag_end(text: &str) -> Option<usize> {
+    let after = text[1..].chars().next()?;
+    if !after.is_ascii_alphabetic() && after != '/' {
+        return None;
+    }
+
+    let mut quote = None;
+    for (i, c) in text.char_indices().skip(1) {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns the `href` attribute of an `a` tag, quoted or not.
+fn href(tag: &str) -> Option<String> {
+    let start = tag.to_ascii_lowercase().find("href=")? + "href=".len();
+    let value = &tag[start..];
+
+    let target = match value.chars().next()? {
+        quote @ ('"' | '\'') => value[1..].split(quote).next()?,
+        _ => value.split_ascii_whitespace().next()?,
+    };
+    Some(target.to_string())
+}
+
+/// Appends `text` with the usual HTML entities decoded.
+fn push_text(plain: &mut String, text: &str) {
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        push_sanitized(plain, &rest[..start]);
+        rest = &rest[start..];
+
+        let decoded = [
+            ("&lt;", '<'),
+            ("&gt;", '>'),
+            ("&amp;", '&'),
+            ("&quot;", '"'),
+            ("&#39;", '\''),
+        ]
+        .iter()
+        .find(|(entity, _)| rest.starts_with(entity));
+        if let Some((entity, c)) = decoded {
+            plain.push(*c);
+            rest = &rest[entity.len()..];
+        } else {
+            plain.push('&');
+            rest = &rest[1..];
+        }
+    }
+    push_sanitized(plain, rest);
+}
+
+/// Appends `text` with tabs turned into spaces, carriage returns dropped and other
+/// control characters except line breaks replaced by U+FFFD.
+fn push_sanitized(plain: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '\n' => plain.push(c),
+            '\t' => plain.push(' '),
+            '\r' => {}
+            c if c.is_control() => plain.push(char::REPLACEMENT_CHARACTER),
+            c => plain.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(text: &str) -> String {
+        parse(text).0
+    }
+
+    #[test]
+    fn unclosed_tags_are_kept_as_text() {
+        for (text, expected) in [
+            ("a < b", "a < b"),
+            ("1<2", "1<2"),
+            ("<", "<"),
+            ("<a href='x'", "<a href='x'"),
+            ("hi <font color=#fff>there", "hi there"),
+        ] {
+            assert_eq!(plain(text), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn quoted_href_can_contain_markup() {
+        let (text, links) = parse("see <a href=\"text://<font color=#fff>x > y</font>\">this</a>!");
+        assert_eq!(text, "see this!");
+        assert_eq!(links.len(), 1);
+        assert_eq!(&text[links[0].range.clone()], "this");
+        assert_eq!(links[0].target, "text://<font color=#fff>x > y</font>");
+        assert_eq!(links[0].describe(), "x > y");
+    }
+
+    #[test]
+    fn unknown_and_malformed_entities_are_kept() {
+        for (text, expected) in [
+            ("&lt;b&gt; &amp; &quot;q&quot; &#39;", "<b> & \"q\" '"),
+            ("&nbsp;", "&nbsp;"),
+            ("AT&T", "AT&T"),
+            ("&lt", "&lt"),
+            ("&amp;lt;", "&lt;"),
+            ("&", "&"),
+        ] {
+            assert_eq!(plain(text), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn multibyte_text_next_to_tags() {
+        let (text, links) = parse("ÄÖ<a href='itemref://1/2/3'>Schwert™</a>日本<br>é");
+        assert_eq!(text, "ÄÖSchwert™日本\né");
+        assert_eq!(&text[links[0].range.clone()], "Schwert™");
+        assert_eq!(plain("日<本"), "日<本");
+        assert_eq!(plain("<ü>"), "<ü>");
+    }
+}
@@ -0,0 +1,44 @@
+use std::{
+    fmt::Debug,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::Path,
+};
+
+use chrono::Local;
+use tokio::sync::mpsc::{channel, Sender};
+
+/// Lines that can be queued before new ones are dropped
+const QUEUE_SIZE: usize = 1024;
+
+/// Appends received packets to a file, one per line with a timestamp. Lines are written
+/// on a blocking thread behind a bounded queue, so a slow disk never stalls the chat task.
+pub struct PacketLog {
+    sender: Sender<String>,
+}
+
+impl PacketLog {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (sender, mut receiver) = channel::<String>(QUEUE_SIZE);
+
+        tokio::task::spawn_blocking(move || {
+            while let Some(line) = receiver.blocking_recv() {
+                if writeln!(file, "{}", line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self { sender })
+    }
+
+    /// Queues `packet` for writing, dropping it if the writer has fallen behind.
+    pub fn log(&self, packet: &impl Debug) {
+        let _ = self.sender.try_send(format!(
+            "{} {:?}",
+            Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+            packet
+        ));
+    }
+}
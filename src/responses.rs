@@ -0,0 +1,123 @@
+use std::{
+    collections::HashMap,
+    fs::read_to_string,
+    io,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// Minutes after an auto-response during which the same character gets no other one
+const COOLDOWN_MINUTES: u64 = 5;
+
+/// Canned answers to tells that contain a keyword, read from a file with one
+/// `keyword=response` rule per line. Empty lines and lines starting with `#` are
+/// skipped, keywords match case-insensitively anywhere in the tell.
+pub struct AutoResponses {
+    rules: Vec<(String, String)>,
+    /// When each character was last answered
+    answered: HashMap<u32, Instant>,
+}
+
+impl AutoResponses {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(Self::parse(&read_to_string(path)?))
+    }
+
+    fn parse(contents: &str) -> Self {
+        let rules = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (keyword, response) = line.split_once('=')?;
+                let keyword = keyword.trim().to_lowercase();
+                let response = response.trim();
+                if keyword.is_empty() || response.is_empty() {
+                    None
+                } else {
+                    Some((keyword, response.to_string()))
+                }
+            })
+            .collect();
+
+        Self {
+            rules,
+            answered: HashMap::new(),
+        }
+    }
+
+    pub fn rule_count(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Returns the response of the first rule whose keyword is in `text`, unless
+    /// `sender` was answered within the cooldown.
+    pub fn respond(&mut self, sender: u32, text: &str) -> Option<String> {
+        self.respond_at(sender, text, Instant::now())
+    }
+
+    fn respond_at(&mut self, sender: u32, text: &str, now: Instant) -> Option<String> {
+        let cooldown = Duration::from_secs(COOLDOWN_MINUTES * 60);
+        if matches!(self.answered.get(&sender), Some(last) if now.duration_since(*last) < cooldown)
+        {
+            return None;
+        }
+
+        let text = text.to_lowercase();
+        let response = self
+            .rules
+            .iter()
+            .find(|(keyword, _)| text.contains(keyword.as_str()))
+            .map(|(_, response)| response.clone())?;
+        self.answered.insert(sender, now);
+        Some(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str =
+        "# comment\n\nPrice = 5m per item\nhello=Hi, I am away\n=no keyword\nempty=\n";
+
+    #[test]
+    fn rules_skip_comments_and_incomplete_lines() {
+        assert_eq!(AutoResponses::parse(RULES).rule_count(), 2);
+    }
+
+    #[test]
+    fn keywords_match_case_insensitively_anywhere() {
+        let now = Instant::now();
+        for (sender, text, expected) in [
+            (1, "What is the PRICE?", Some("5m per item")),
+            (2, "oh hello there", Some("Hi, I am away")),
+            (3, "nothing to see", None),
+        ] {
+            let mut responses = AutoResponses::parse(RULES);
+            assert_eq!(
+                responses.respond_at(sender, text, now).as_deref(),
+                expected,
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn same_sender_is_answered_again_after_the_cooldown() {
+        let mut responses = AutoResponses::parse(RULES);
+        let now = Instant::now();
+        let cooldown = Duration::from_secs(COOLDOWN_MINUTES * 60);
+
+        assert!(responses.respond_at(1, "hello", now).is_some());
+        assert!(responses
+            .respond_at(1, "hello", now + cooldown / 2)
+            .is_none());
+        // Other characters are not held back by it
+        assert!(responses
+            .respond_at(2, "hello", now + cooldown / 2)
+            .is_some());
+        assert!(responses.respond_at(1, "hello", now + cooldown).is_some());
+    }
+}
@@ -1,26 +1,59 @@
-use std::io;
+use std::io::{self, Write};
 pub use tui::{backend::CrosstermBackend, Terminal};
 
 use crossterm::{
+    cursor::MoveTo,
     event::{DisableMouseCapture, EnableMouseCapture},
     terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
 };
 
-pub fn init_crossterm() -> io::Result<(Terminal<CrosstermBackend<io::Stdout>>, OnShutdown)> {
+/// Sets up the terminal. Without `alt_screen` the UI is drawn inline, after pushing
+/// whatever is on screen into the terminal's own scrollback.
+pub fn init_crossterm(
+    alt_screen: bool,
+) -> io::Result<(Terminal<CrosstermBackend<io::Stdout>>, OnShutdown)> {
     terminal::enable_raw_mode()?;
 
     let mut stdout = std::io::stdout();
-    crossterm::execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    if alt_screen {
+        crossterm::execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    } else {
+        let (_, rows) = terminal::size()?;
+        crossterm::execute!(
+            stdout,
+            MoveTo(0, rows.saturating_sub(1)),
+            EnableMouseCapture
+        )?;
+        stdout.write_all("\r\n".repeat(rows.into()).as_bytes())?;
+        stdout.flush()?;
+    }
     let backend = CrosstermBackend::new(io::stdout());
-    let term = Terminal::new(backend)?;
-
-    let cleanup = OnShutdown::new(|| {
-        // Be a good terminal citizen...
-        let mut stdout = std::io::stdout();
-        crossterm::execute!(stdout, LeaveAlternateScreen, DisableMouseCapture)?;
-        terminal::disable_raw_mode()?;
-        Ok(())
-    });
+    let mut term = Terminal::new(backend)?;
+    term.clear()?;
+
+    let cleanup = if alt_screen {
+        OnShutdown::new(|| {
+            // Be a good terminal citizen...
+            let mut stdout = std::io::stdout();
+            crossterm::execute!(stdout, LeaveAlternateScreen, DisableMouseCapture)?;
+            terminal::disable_raw_mode()?;
+            Ok(())
+        })
+    } else {
+        OnShutdown::new(|| {
+            // Leave the last frame in place and put the shell prompt below it
+            let mut stdout = std::io::stdout();
+            let (_, rows) = terminal::size()?;
+            crossterm::execute!(
+                stdout,
+                DisableMouseCapture,
+                MoveTo(0, rows.saturating_sub(1))
+            )?;
+            terminal::disable_raw_mode()?;
+            writeln!(stdout)?;
+            Ok(())
+        })
+    };
 
     Ok((term, cleanup))
 }
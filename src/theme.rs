@@ -0,0 +1,324 @@
+use std::{convert::TryFrom, env};
+
+use tui::style::Color;
+
+/// Levels of the six steps per channel in the 256-color cube
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// The 16 basic colors as xterm draws them by default
+const BASIC_COLORS: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::Gray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+/// How many colors the terminal can show.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ColorSupport {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+}
+
+impl ColorSupport {
+    /// Guesses the color support from `$COLORTERM` and `$TERM`, the way most
+    /// terminals and SSH sessions announce it.
+    pub fn detect() -> Self {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let term = env::var("TERM").unwrap_or_default();
+
+        if colorterm == "truecolor" || colorterm == "24bit" || term.contains("direct") {
+            Self::TrueColor
+        } else if term.contains("256color") {
+            Self::Ansi256
+        } else {
+            Self::Ansi16
+        }
+    }
+
+    /// The closest color to `color` that the terminal can show.
+    fn reduce(self, color: Color) -> Color {
+        match (self, color) {
+            (Self::Ansi256, Color::Rgb(r, g, b)) => Color::Indexed(nearest_indexed(r, g, b)),
+            (Self::Ansi16, Color::Rgb(r, g, b)) => nearest_basic(r, g, b),
+            (Self::Ansi16, Color::Indexed(index)) => {
+                let (r, g, b) = indexed_rgb(index);
+                nearest_basic(r, g, b)
+            }
+            _ => color,
+        }
+    }
+}
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let d = |a: u8, b: u8| u32::from(a.abs_diff(b)).pow(2);
+    d(r1, r2) + d(g1, g2) + d(b1, b2)
+}
+
+/// Index of the step in `CUBE_LEVELS` that is closest to `value`.
+fn cube_step(value: u8) -> u8 {
+    (0..6)
+        .min_by_key(|&step| CUBE_LEVELS[usize::from(step)].abs_diff(value))
+        .unwrap_or_default()
+}
+
+/// The entry of the 256-color palette closest to the color, from either the color
+/// cube or the grayscale ramp.
+fn nearest_indexed(r: u8, g: u8, b: u8) -> u8 {
+    let cube = 16 + 36 * cube_step(r) + 6 * cube_step(g) + cube_step(b);
+    let average = (u16::from(r) + u16::from(g) + u16::from(b)) / 3;
+    // The ramp runs from 8 to 238 in steps of 10
+    let gray = 232 + u8::try_from((average.saturating_sub(3) / 10).min(23)).unwrap_or(23);
+
+    let rgb = (r, g, b);
+    if distance(indexed_rgb(gray), rgb) < distance(indexed_rgb(cube), rgb) {
+        gray
+    } else {
+        cube
+    }
+}
+
+/// The color an entry of the 256-color palette stands for.
+fn indexed_rgb(index: u8) -> (u8, u8, u8) {
+    match index {
+        0..=15 => BASIC_COLORS[usize::from(index)].1,
+        16..=231 => {
+            let index = index - 16;
+            let level = |step: u8| CUBE_LEVELS[usize::from(step)];
+            (level(index / 36), level(index / 6 % 6), level(index % 6))
+        }
+        _ => {
+            let level = 8 + (index - 232) * 10;
+            (level, level, level)
+        }
+    }
+}
+
+fn nearest_basic(r: u8, g: u8, b: u8) -> Color {
+    BASIC_COLORS
+        .iter()
+        .min_by_key(|(_, rgb)| distance(*rgb, (r, g, b)))
+        .map_or(Color::Reset, |(color, _)| *color)
+}
+
+#[derive(Clone)]
+pub struct Theme {
+    pub background: Color,
+    pub foreground: Color,
+    pub command_bar: Color,
+    pub command_bar_text: Color,
+    pub scroll_bar: Color,
+    pub scroll_bar_text: Color,
+    pub chat_bar: Color,
+    pub chat_bar_text: Color,
+    pub input_bar: Color,
+    pub input_bar_text: Color,
+    pub tell: Color,
+    pub outgoing: Color,
+    pub system: Color,
+    pub highlight: Color,
+    pub warning: Color,
+    pub link: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            background: Color::Rgb(51, 51, 51),
+            foreground: Color::LightYellow,
+            command_bar: Color::Rgb(232, 149, 6),
+            command_bar_text: Color::Black,
+            scroll_bar: Color::Red,
+            scroll_bar_text: Color::White,
+            chat_bar: Color::Blue,
+            chat_bar_text: Color::White,
+            input_bar: Color::Black,
+            input_bar_text: Color::White,
+            tell: Color::LightMagenta,
+            outgoing: Color::LightCyan,
+            system: Color::Gray,
+            highlight: Color::LightGreen,
+            warning: Color::LightRed,
+            link: Color::LightBlue,
+        }
+    }
+}
+
+impl Theme {
+    /// Builds the theme from the `COLOR_*` config keys, falling back to the default for unset slots.
+    /// Returns the first key with an invalid color on failure.
+    pub fn load<'a>(get: impl Fn(&str) -> Option<&'a str>) -> Result<Self, &'static str> {
+        let mut theme = Self::default();
+
+        for (key, slot) in [
+            ("COLOR_BACKGROUND", &mut theme.background),
+            ("COLOR_FOREGROUND", &mut theme.foreground),
+            ("COLOR_COMMAND_BAR", &mut theme.command_bar),
+            ("COLOR_COMMAND_BAR_TEXT", &mut theme.command_bar_text),
+            ("COLOR_SCROLL_BAR", &mut theme.scroll_bar),
+            ("COLOR_SCROLL_BAR_TEXT", &mut theme.scroll_bar_text),
+            ("COLOR_CHAT_BAR", &mut theme.chat_bar),
+            ("COLOR_CHAT_BAR_TEXT", &mut theme.chat_bar_text),
+            ("COLOR_INPUT_BAR", &mut theme.input_bar),
+            ("COLOR_INPUT_BAR_TEXT", &mut theme.input_bar_text),
+            ("COLOR_TELL", &mut theme.tell),
+            ("COLOR_OUTGOING", &mut theme.outgoing),
+            ("COLOR_SYSTEM", &mut theme.system),
+            ("COLOR_HIGHLIGHT", &mut theme.highlight),
+            ("COLOR_WARNING", &mut theme.warning),
+            ("COLOR_LINK", &mut theme.link),
+        ] {
+            if let Some(value) = get(key).filter(|v| !v.is_empty()) {
+                *slot = parse_color(value).ok_or(key)?;
+            }
+        }
+
+        Ok(theme)
+    }
+
+    /// The theme with every color replaced by the closest one the terminal can show.
+    pub fn reduced(&self, support: ColorSupport) -> Self {
+        let reduce = |color| support.reduce(color);
+        Self {
+            background: reduce(self.background),
+            foreground: reduce(self.foreground),
+            command_bar: reduce(self.command_bar),
+            command_bar_text: reduce(self.command_bar_text),
+            scroll_bar: reduce(self.scroll_bar),
+            scroll_bar_text: reduce(self.scroll_bar_text),
+            chat_bar: reduce(self.chat_bar),
+            chat_bar_text: reduce(self.chat_bar_text),
+            input_bar: reduce(self.input_bar),
+            input_bar_text: reduce(self.input_bar_text),
+            tell: reduce(self.tell),
+            outgoing: reduce(self.outgoing),
+            system: reduce(self.system),
+            highlight: reduce(self.highlight),
+            warning: reduce(self.warning),
+            link: reduce(self.link),
+        }
+    }
+}
+
+/// Parses `r,g,b`, `#rrggbb` or a named terminal color.
+fn parse_color(value: &str) -> Option<Color> {
+    let value = value.trim();
+
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        return Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?));
+    }
+
+    if value.contains(',') {
+        let mut parts = value.split(',').map(|p| p.trim().parse::<u8>());
+        let color = Color::Rgb(
+            parts.next()?.ok()?,
+            parts.next()?.ok()?,
+            parts.next()?.ok()?,
+        );
+        return if parts.next().is_none() {
+            Some(color)
+        } else {
+            None
+        };
+    }
+
+    let color = match value.to_ascii_lowercase().as_str() {
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" => Color::Gray,
+        "darkgray" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        "reset" => Color::Reset,
+        _ => return None,
+    };
+
+    Some(color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgb_maps_to_the_nearest_256_color() {
+        for ((r, g, b), expected) in [
+            ((0, 0, 0), 16),
+            ((255, 255, 255), 231),
+            ((255, 0, 0), 196),
+            ((0, 0, 255), 21),
+            ((232, 149, 6), 172),
+            // Grays are closer on the grayscale ramp than in the cube
+            ((51, 51, 51), 236),
+            ((128, 128, 128), 244),
+        ] {
+            assert_eq!(nearest_indexed(r, g, b), expected, "{:?}", (r, g, b));
+        }
+    }
+
+    #[test]
+    fn indexed_colors_round_trip() {
+        for index in 16..=255 {
+            let (r, g, b) = indexed_rgb(index);
+            assert_eq!(nearest_indexed(r, g, b), index);
+        }
+    }
+
+    #[test]
+    fn rgb_maps_to_the_nearest_basic_color() {
+        for ((r, g, b), expected) in [
+            ((0, 0, 0), Color::Black),
+            ((250, 250, 250), Color::White),
+            ((200, 10, 10), Color::Red),
+            ((51, 51, 51), Color::Black),
+            ((120, 120, 130), Color::DarkGray),
+            ((90, 90, 250), Color::LightBlue),
+        ] {
+            assert_eq!(nearest_basic(r, g, b), expected, "{:?}", (r, g, b));
+        }
+    }
+
+    #[test]
+    fn reduce_leaves_colors_the_terminal_can_show() {
+        assert_eq!(
+            ColorSupport::TrueColor.reduce(Color::Rgb(1, 2, 3)),
+            Color::Rgb(1, 2, 3)
+        );
+        assert_eq!(
+            ColorSupport::Ansi256.reduce(Color::Indexed(42)),
+            Color::Indexed(42)
+        );
+        assert_eq!(
+            ColorSupport::Ansi16.reduce(Color::Indexed(196)),
+            Color::LightRed
+        );
+        assert_eq!(ColorSupport::Ansi16.reduce(Color::Blue), Color::Blue);
+    }
+}
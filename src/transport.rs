@@ -0,0 +1,189 @@
+use std::{fmt::Debug, future::Future, pin::Pin};
+
+use nadylib::{
+    client_socket::SocketSendHandle, packets::OutgoingPacket, AOSocket, ReceivedPacket,
+    SocketConfig,
+};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Sends packets to the server, also from tasks other than the one reading.
+pub trait PacketSender: Clone + Send + Sync + 'static {
+    fn send<P>(&self, packet: P) -> BoxFuture<'_, nadylib::Result<()>>
+    where
+        P: OutgoingPacket + Debug + Send + 'static;
+}
+
+/// A connection to the server that the chat task reads packets from.
+pub trait PacketSocket: Send {
+    type Sender: PacketSender;
+
+    /// Reads the next packet, or returns `None` once the connection is lost.
+    fn read_packet(&mut self) -> BoxFuture<'_, Option<ReceivedPacket>>;
+
+    fn login<'a>(
+        &'a mut self,
+        user_name: &'a str,
+        password: &'a str,
+        login_seed: &'a str,
+    ) -> BoxFuture<'a, nadylib::Result<()>>;
+
+    fn sender(&self) -> Self::Sender;
+}
+
+/// Opens connections, so the chat task can be run against scripted sockets in tests.
+pub trait Connector: Send + Sync + 'static {
+    type Socket: PacketSocket;
+
+    /// Connects to `server`, or returns `None` if that failed.
+    fn connect<'a>(&'a self, server: &'a str) -> BoxFuture<'a, Option<Self::Socket>>;
+}
+
+impl PacketSender for SocketSendHandle {
+    fn send<P>(&self, packet: P) -> BoxFuture<'_, nadylib::Result<()>>
+    where
+        P: OutgoingPacket + Debug + Send + 'static,
+    {
+        Box::pin(SocketSendHandle::send(self, packet))
+    }
+}
+
+impl PacketSocket for AOSocket {
+    type Sender = SocketSendHandle;
+
+    fn read_packet(&mut self) -> BoxFuture<'_, Option<ReceivedPacket>> {
+        Box::pin(async move { AOSocket::read_packet(self).await.ok() })
+    }
+
+    fn login<'a>(
+        &'a mut self,
+        user_name: &'a str,
+        password: &'a str,
+        login_seed: &'a str,
+    ) -> BoxFuture<'a, nadylib::Result<()>> {
+        Box::pin(AOSocket::login(self, user_name, password, login_seed))
+    }
+
+    fn sender(&self) -> SocketSendHandle {
+        self.get_sender()
+    }
+}
+
+/// Connects to real chat servers.
+pub struct AoConnector;
+
+impl Connector for AoConnector {
+    type Socket = AOSocket;
+
+    fn connect<'a>(&'a self, server: &'a str) -> BoxFuture<'a, Option<AOSocket>> {
+        Box::pin(async move {
+            AOSocket::connect(server, SocketConfig::default())
+                .await
+                .ok()
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use std::{collections::VecDeque, fmt::Debug, future::pending, sync::Mutex};
+
+    use nadylib::{packets::OutgoingPacket, ReceivedPacket};
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+    use super::{BoxFuture, Connector, PacketSender, PacketSocket};
+
+    /// Records sent packets in their `Debug` form, and logins as `login <user name>`.
+    #[derive(Clone)]
+    pub struct MockSender {
+        sent: UnboundedSender<String>,
+    }
+
+    impl PacketSender for MockSender {
+        fn send<P>(&self, packet: P) -> BoxFuture<'_, nadylib::Result<()>>
+        where
+            P: OutgoingPacket + Debug + Send + 'static,
+        {
+            let _ = self.sent.send(format!("{:?}", packet));
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    /// Reads packets fed in by the test. Dropping the feeding end loses the connection.
+    pub struct MockSocket {
+        packets: UnboundedReceiver<ReceivedPacket>,
+        sender: MockSender,
+    }
+
+    impl PacketSocket for MockSocket {
+        type Sender = MockSender;
+
+        fn read_packet(&mut self) -> BoxFuture<'_, Option<ReceivedPacket>> {
+            Box::pin(self.packets.recv())
+        }
+
+        fn login<'a>(
+            &'a mut self,
+            user_name: &'a str,
+            _password: &'a str,
+            _login_seed: &'a str,
+        ) -> BoxFuture<'a, nadylib::Result<()>> {
+            let _ = self.sender.sent.send(format!("login {}", user_name));
+            Box::pin(async { Ok(()) })
+        }
+
+        fn sender(&self) -> MockSender {
+            self.sender.clone()
+        }
+    }
+
+    /// Hands out its sockets in order, then never connects again.
+    pub struct MockConnector {
+        sockets: Mutex<VecDeque<MockSocket>>,
+    }
+
+    impl Connector for MockConnector {
+        type Socket = MockSocket;
+
+        fn connect<'a>(&'a self, _server: &'a str) -> BoxFuture<'a, Option<MockSocket>> {
+            let socket = self.sockets.lock().unwrap().pop_front();
+            Box::pin(async move {
+                match socket {
+                    Some(socket) => Some(socket),
+                    None => pending().await,
+                }
+            })
+        }
+    }
+
+    /// Creates a connector with `count` sockets. Returns the ends feeding each socket
+    /// with packets, and one receiving everything sent on any of them.
+    pub fn connector(
+        count: usize,
+    ) -> (
+        MockConnector,
+        Vec<UnboundedSender<ReceivedPacket>>,
+        UnboundedReceiver<String>,
+    ) {
+        let (sent, sent_receiver) = unbounded_channel();
+        let mut sockets = VecDeque::new();
+        let mut feeds = Vec::new();
+
+        for _ in 0..count {
+            let (feed, packets) = unbounded_channel();
+            sockets.push_back(MockSocket {
+                packets,
+                sender: MockSender { sent: sent.clone() },
+            });
+            feeds.push(feed);
+        }
+
+        (
+            MockConnector {
+                sockets: Mutex::new(sockets),
+            },
+            feeds,
+            sent_receiver,
+        )
+    }
+}
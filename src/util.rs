@@ -1,27 +1,211 @@
-use tui::layout::{Constraint, Direction, Layout, Rect};
-
-pub fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
-    let popup_layout = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints(
-            [
-                Constraint::Percentage((100 - percent_y) / 2),
-                Constraint::Percentage(percent_y),
-                Constraint::Percentage((100 - percent_y) / 2),
-            ]
-            .as_ref(),
-        )
-        .split(r);
-
-    Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints(
-            [
-                Constraint::Percentage((100 - percent_x) / 2),
-                Constraint::Percentage(percent_x),
-                Constraint::Percentage((100 - percent_x) / 2),
-            ]
-            .as_ref(),
-        )
-        .split(popup_layout[1])[1]
+use std::convert::TryFrom;
+
+use tui::{
+    layout::Rect,
+    style::Style,
+    text::{Span, Spans},
+    widgets::ListState,
+};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Converts a length to terminal cells, saturating instead of wrapping around.
+pub fn cells(length: usize) -> u16 {
+    u16::try_from(length).unwrap_or(u16::MAX)
+}
+
+/// Places the cursor after `text` in `area`, keeping it inside the area.
+pub fn cursor_after(text: &str, area: Rect) -> (u16, u16) {
+    let offset = cells(text.width()).min(area.width.saturating_sub(1));
+    (area.x + offset, area.y)
+}
+
+/// Shortens `text` with an ellipsis so that it takes up at most `width` terminal columns.
+pub fn truncate_to_width(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+
+    let mut truncated = String::new();
+    let mut used = 0;
+    // Reserve one column for the ellipsis
+    let budget = width.saturating_sub(1);
+
+    for c in text.chars() {
+        let char_width = c.width().unwrap_or(0);
+        if used + char_width > budget {
+            break;
+        }
+        used += char_width;
+        truncated.push(c);
+    }
+
+    if width > 0 {
+        truncated.push('…');
+    }
+
+    truncated
+}
+
+/// Styles the characters of `text` at the given char `indices`.
+pub fn highlight_chars<'a>(text: &str, indices: &[usize], style: Style) -> Spans<'a> {
+    text.chars()
+        .enumerate()
+        .map(|(i, c)| {
+            if indices.contains(&i) {
+                Span::styled(c.to_string(), style)
+            } else {
+                Span::raw(c.to_string())
+            }
+        })
+        .collect::<Vec<Span>>()
+        .into()
+}
+
+/// Moves the selection of a list with `len` items one up or down, wrapping around
+/// at either end. Without a selection, the first item is selected.
+pub fn step_selection(state: &mut ListState, len: usize, up: bool) {
+    let i = match state.selected() {
+        Some(i) if up => {
+            if i == 0 {
+                len.saturating_sub(1)
+            } else {
+                i - 1
+            }
+        }
+        Some(i) => {
+            if i + 1 >= len {
+                0
+            } else {
+                i + 1
+            }
+        }
+        None => 0,
+    };
+    state.select(Some(i));
+}
+
+/// Keeps the selection of a list with `len` items on an item: the first one if nothing
+/// is selected, the last one if the list shrank below the selection. tui scrolls the
+/// list to keep the selection visible, so this also keeps the scroll offset valid.
+pub fn fit_selection(state: &mut ListState, len: usize) {
+    match state.selected() {
+        _ if len == 0 => state.select(None),
+        None => state.select(Some(0)),
+        Some(i) if i >= len => state.select(Some(len - 1)),
+        Some(_) => {}
+    }
+}
+
+/// Adds the position of the selection to the title of a bordered popup list, if its
+/// `len` items don't all fit into `area`.
+pub fn list_title(title: String, state: &ListState, len: usize, area: Rect) -> String {
+    let rows = usize::from(area.height.saturating_sub(2));
+    match state.selected() {
+        Some(i) if len > rows => format!("{} ({}/{})", title, i + 1, len),
+        _ => title,
+    }
+}
+
+/// Centers a rect of `percent_x` by `percent_y` percent of `r` in it. The result is at
+/// least `min_width` by `min_height` as far as `r` allows, and never zero-sized unless `r` is.
+pub fn centered_rect(
+    percent_x: u16,
+    percent_y: u16,
+    min_width: u16,
+    min_height: u16,
+    r: Rect,
+) -> Rect {
+    let scale = |length: u16, percent: u16, min: u16| {
+        let scaled = u32::from(length) * u32::from(percent.min(100)) / 100;
+        cells(scaled as usize).max(min.max(1)).min(length)
+    };
+    let width = scale(r.width, percent_x, min_width);
+    let height = scale(r.height, percent_y, min_height);
+
+    Rect {
+        x: r.x + (r.width - width) / 2,
+        y: r.y + (r.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+/// Encodes `data` as standard base64 with padding.
+pub fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(data.len() * 4 / 3 + 4);
+    for chunk in data.chunks(3) {
+        let byte = |i: usize| u32::from(chunk.get(i).copied().unwrap_or(0));
+        let group = (byte(0) << 16) | (byte(1) << 8) | byte(2);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (group >> (18 - 6 * i)) & 63;
+                encoded.push(char::from(ALPHABET[index as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Number of rows `line` takes up once wrapped to `width` columns. Wrapping at words
+/// can take a little more, so this is a lower bound for long lines.
+pub fn wrapped_rows(line: &Spans, width: usize) -> usize {
+    if width == 0 {
+        return 1;
+    }
+    line.width().saturating_sub(1) / width + 1
+}
+
+/// Builds a vertical scrollbar of `height` rows for a view showing `height` of `total`
+/// lines, starting at line `offset`.
+pub fn scrollbar<'a>(total: usize, offset: usize, height: usize) -> Vec<Spans<'a>> {
+    if height == 0 || total <= height {
+        return Vec::new();
+    }
+
+    let thumb = (height * height / total).max(1);
+    let max_offset = total - height;
+    let start = offset.min(max_offset) * (height - thumb) / max_offset;
+
+    (0..height)
+        .map(|row| {
+            if row >= start && row < start + thumb {
+                Spans::from("█")
+            } else {
+                Spans::from("│")
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_pads_every_remainder() {
+        // The test vectors of RFC 4648, section 10
+        for (input, expected) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(base64(input.as_bytes()), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn base64_uses_the_whole_alphabet() {
+        assert_eq!(base64(&[0xfb, 0xff, 0xbf]), "+/+/");
+        assert_eq!(base64(&[0, 0, 0]), "AAAA");
+        assert_eq!(base64("ünï".as_bytes()), "w7xuw68=");
+    }
 }